@@ -0,0 +1,354 @@
+use darling::{ast::NestedMeta, FromMeta};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{spanned::Spanned, FnArg, Item, ItemMod, Pat, ReturnType, Type};
+
+use crate::typescript::ts_type;
+
+const EXPORT_ATTR: &str = "js_export";
+
+#[derive(Debug, Default, FromMeta)]
+struct ModuleArgs {
+    /// name of the module and of the global it is registered as, defaults to the rust module name
+    #[darling(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Default, FromMeta)]
+struct ExportArgs {
+    #[darling(default)]
+    rename: Option<String>,
+}
+
+/// an item marked with `#[js_export]`
+enum Export {
+    Function {
+        name: String,
+        ident: syn::Ident,
+        is_async: bool,
+        sig: Box<syn::Signature>,
+    },
+    Class {
+        name: String,
+        ident: syn::Ident,
+    },
+}
+
+pub(crate) fn process_js_module(args: TokenStream, input: ItemMod) -> TokenStream {
+    expand_js_module(args, input).unwrap_or_else(|e| e.write_errors())
+}
+
+pub(crate) fn process_js_export(item: TokenStream) -> TokenStream {
+    let err = darling::Error::custom("`#[js_export]` can only be used inside a `#[js_module]`")
+        .with_span(&item)
+        .write_errors();
+    quote! {
+        #err
+        #item
+    }
+}
+
+fn expand_js_module(args: TokenStream, mut input: ItemMod) -> darling::Result<TokenStream> {
+    let args = ModuleArgs::from_list(&NestedMeta::parse_meta_list(args)?)?;
+    let module_name = args.name.unwrap_or_else(|| input.ident.to_string());
+    let Some((_, items)) = input.content.as_mut() else {
+        return Err(
+            darling::Error::custom("`#[js_module]` requires an inline module body")
+                .with_span(&input.ident),
+        );
+    };
+
+    let mut errors = darling::Error::accumulator();
+    let mut exports = Vec::new();
+    for item in items.iter_mut() {
+        if let Some(export) = errors.handle(take_export(item)).flatten() {
+            exports.push(export);
+        }
+    }
+    errors.finish()?;
+
+    let names: Vec<_> = exports.iter().map(Export::name).collect();
+    let values = exports.iter().map(Export::value);
+    let declaration = declaration(&module_name, &exports);
+
+    let generated: TokenStream = quote! {
+        /// QuickJS module generated by `#[js_module]`
+        pub struct JsModule;
+
+        impl JsModule {
+            pub const NAME: &'static str = #module_name;
+
+            /// TypeScript declaration of the global created by [`JsModule::register`]
+            pub const TS_DECLARATION: &'static str = #declaration;
+
+            /// expose the exports as a global object named after the module
+            pub fn register<'js>(ctx: &rquickjs::Ctx<'js>) -> rquickjs::Result<()> {
+                let obj = rquickjs::Object::new(ctx.clone())?;
+                for (name, value) in Self::exports(ctx)? {
+                    obj.set(name, value)?;
+                }
+                ctx.globals().set(Self::NAME, obj)
+            }
+
+            fn exports<'js>(
+                ctx: &rquickjs::Ctx<'js>,
+            ) -> rquickjs::Result<Vec<(&'static str, rquickjs::Value<'js>)>> {
+                Ok(vec![#((#names, #values)),*])
+            }
+        }
+
+        impl rquickjs::module::ModuleDef for JsModule {
+            fn declare<'js>(decl: &rquickjs::module::Declarations<'js>) -> rquickjs::Result<()> {
+                #(decl.declare(#names)?;)*
+                Ok(())
+            }
+
+            fn evaluate<'js>(
+                ctx: &rquickjs::Ctx<'js>,
+                exports: &rquickjs::module::Exports<'js>,
+            ) -> rquickjs::Result<()> {
+                for (name, value) in Self::exports(ctx)? {
+                    exports.export(name, value)?;
+                }
+                Ok(())
+            }
+        }
+    };
+    items.push(Item::Verbatim(generated));
+
+    Ok(quote! { #input })
+}
+
+/// strip the `#[js_export]` attribute from an item, describing what it exports
+fn take_export(item: &mut Item) -> darling::Result<Option<Export>> {
+    let span = item.span();
+    let attrs = match item {
+        Item::Fn(f) => &mut f.attrs,
+        Item::Struct(s) => &mut s.attrs,
+        Item::Enum(e) => &mut e.attrs,
+        Item::Const(c) => &mut c.attrs,
+        Item::Static(s) => &mut s.attrs,
+        Item::Type(t) => &mut t.attrs,
+        Item::Impl(i) => &mut i.attrs,
+        Item::Trait(t) => &mut t.attrs,
+        Item::Mod(m) => &mut m.attrs,
+        _ => return Ok(None),
+    };
+    let Some(pos) = attrs.iter().position(|a| a.path().is_ident(EXPORT_ATTR)) else {
+        return Ok(None);
+    };
+    let attr = attrs.remove(pos);
+    let args = match &attr.meta {
+        syn::Meta::Path(_) => ExportArgs::default(),
+        meta => ExportArgs::from_meta(meta)?,
+    };
+
+    match item {
+        Item::Fn(f) => {
+            let sig = &f.sig;
+            if let Some(FnArg::Receiver(receiver)) = sig.inputs.first() {
+                return Err(darling::Error::custom(
+                    "methods can not be exported, use a free function",
+                )
+                .with_span(receiver));
+            }
+            if sig.generics.type_params().next().is_some() {
+                return Err(
+                    darling::Error::custom("generic functions can not be exported")
+                        .with_span(&sig.generics),
+                );
+            }
+            Ok(Some(Export::Function {
+                name: args.rename.unwrap_or_else(|| sig.ident.to_string()),
+                ident: sig.ident.clone(),
+                is_async: sig.asyncness.is_some(),
+                sig: Box::new(sig.clone()),
+            }))
+        }
+        Item::Struct(syn::ItemStruct { ident, .. }) => Ok(Some(Export::Class {
+            name: args.rename.unwrap_or_else(|| ident.to_string()),
+            ident: ident.clone(),
+        })),
+        _ => Err(darling::Error::custom(
+            "only functions and `#[rquickjs::class]` structs can be exported",
+        )
+        .with_span(&span)),
+    }
+}
+
+impl Export {
+    fn name(&self) -> &str {
+        match self {
+            Export::Function { name, .. } | Export::Class { name, .. } => name,
+        }
+    }
+
+    /// expression creating the js value of the export
+    fn value(&self) -> TokenStream {
+        match self {
+            Export::Function {
+                name,
+                ident,
+                is_async: false,
+                ..
+            } => quote! {
+                rquickjs::Function::new(ctx.clone(), #ident)?
+                    .with_name(#name)?
+                    .into_value()
+            },
+            Export::Function { name, ident, .. } => quote! {
+                rquickjs::Function::new(ctx.clone(), rquickjs::prelude::Async(#ident))?
+                    .with_name(#name)?
+                    .into_value()
+            },
+            Export::Class { ident, .. } => quote! {
+                rquickjs::Class::<#ident>::create_constructor(ctx)?
+                    .ok_or_else(|| {
+                        rquickjs::Error::new_into_js(stringify!(#ident), "constructor")
+                    })?
+                    .into_value()
+            },
+        }
+    }
+}
+
+fn declaration(module: &str, exports: &[Export]) -> String {
+    let members: String = exports
+        .iter()
+        .map(|export| match export {
+            Export::Function {
+                name,
+                is_async,
+                sig,
+                ..
+            } => {
+                let ret = match &sig.output {
+                    ReturnType::Default => "void".to_string(),
+                    ReturnType::Type(_, ty) => ts_type(ty),
+                };
+                let ret = if *is_async {
+                    format!("Promise<{ret}>")
+                } else {
+                    ret
+                };
+                format!("  {name}({}): {ret};\n", params(sig).join(", "))
+            }
+            Export::Class { name, ident } => {
+                format!("  {name}: new (...args: any[]) => {ident};\n")
+            }
+        })
+        .collect();
+    format!("declare const {module}: {{\n{members}}};\n")
+}
+
+/// TypeScript parameters, leaving out the ones injected by rquickjs
+fn params(sig: &syn::Signature) -> Vec<String> {
+    sig.inputs
+        .iter()
+        .filter_map(|arg| match arg {
+            FnArg::Typed(arg) => Some(arg),
+            FnArg::Receiver(_) => None,
+        })
+        .enumerate()
+        .filter_map(|(i, arg)| {
+            let name = match arg.pat.as_ref() {
+                Pat::Ident(pat) => pat.ident.to_string(),
+                _ => format!("arg{i}"),
+            };
+            let (wrapper, inner) = wrapper(&arg.ty);
+            match wrapper.as_deref() {
+                Some("Ctx" | "This" | "Func") => None,
+                Some("Opt") => Some(format!("{name}?: {}", inner.map(ts_type)?)),
+                Some("Rest") => Some(format!("...{name}: {}[]", inner.map(ts_type)?)),
+                _ => Some(format!("{name}: {}", ts_type(&arg.ty))),
+            }
+        })
+        .collect()
+}
+
+/// the last path segment of a type and its first type argument
+fn wrapper(ty: &Type) -> (Option<String>, Option<&Type>) {
+    let Type::Path(path) = ty else {
+        return (None, None);
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return (None, None);
+    };
+    let inner = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+    (Some(segment.ident.to_string()), inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_js_module_should_work() {
+        let input: ItemMod = syn::parse_str(
+            r#"
+            mod auth {
+                #[js_export]
+                pub fn hello(name: String) -> String {
+                    format!("hello {name}")
+                }
+
+                #[js_export(rename = "verifyToken")]
+                pub async fn verify(ctx: Ctx<'_>, token: String, strict: Opt<bool>) -> rquickjs::Result<bool> {
+                    Ok(!token.is_empty())
+                }
+
+                fn helper() {}
+            }
+            "#,
+        )
+        .unwrap();
+        let args = quote! { name = "authClient" };
+        let code = process_js_module(args, input).to_string();
+        assert!(!code.contains("js_export"));
+        assert!(code.contains("rquickjs :: prelude :: Async (verify)"));
+
+        let input: ItemMod = syn::parse_str(
+            r#"
+            mod auth {
+                #[js_export]
+                pub fn hello(name: String) -> String { name }
+                #[js_export(rename = "verifyToken")]
+                pub async fn verify(ctx: Ctx<'_>, token: String, strict: Opt<bool>) -> rquickjs::Result<bool> { Ok(true) }
+            }
+            "#,
+        )
+        .unwrap();
+        let Some((_, items)) = input.content.clone() else {
+            unreachable!()
+        };
+        let exports: Vec<_> = items
+            .into_iter()
+            .filter_map(|mut item| take_export(&mut item).unwrap())
+            .collect();
+        assert_eq!(
+            declaration("auth", &exports),
+            "declare const auth: {\n  hello(name: string): string;\n  verifyToken(token: string, strict?: boolean): Promise<boolean>;\n};\n"
+        );
+    }
+
+    #[test]
+    fn misuse_should_be_compile_errors() {
+        let inputs = [
+            "mod auth;",
+            "mod auth { #[js_export] const A: u8 = 1; }",
+            "mod auth { #[js_export] fn generic<T>(v: T) {} }",
+            "mod auth { #[js_export(name = \"x\")] fn hello() {} }",
+        ];
+        for input in inputs {
+            let parsed: ItemMod = syn::parse_str(input).unwrap();
+            let code = process_js_module(TokenStream::new(), parsed).to_string();
+            assert!(code.contains("compile_error"), "{input}");
+        }
+    }
+}
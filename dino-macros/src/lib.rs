@@ -1,15 +1,42 @@
+mod js_module;
 mod process_js;
+mod rename;
+mod typescript;
 
+use js_module::{process_js_export, process_js_module};
 use proc_macro::TokenStream;
 use process_js::{process_from_js, process_into_js};
+use typescript::process_typescript;
 
-#[proc_macro_derive(IntoJs)]
+#[proc_macro_derive(IntoJs, attributes(js))]
 pub fn derive_into_js(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
     process_into_js(input).into()
 }
-#[proc_macro_derive(FromJs)]
+#[proc_macro_derive(FromJs, attributes(js))]
 pub fn derive_from_js(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
     process_from_js(input).into()
 }
+
+/// Generate a `TS_DECLARATION` const describing the type as seen from js.
+/// The `js` attributes are shared with `IntoJs` and `FromJs` so the shapes agree.
+#[proc_macro_derive(TypeScript, attributes(js))]
+pub fn derive_typescript(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    process_typescript(input).into()
+}
+
+/// Turn an inline module into a QuickJS module exposing its `#[js_export]` items.
+/// Sync and async functions become js functions, `#[rquickjs::class]` structs become constructors.
+#[proc_macro_attribute]
+pub fn js_module(args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::ItemMod);
+    process_js_module(args.into(), input).into()
+}
+
+/// Mark an item of a `#[js_module]` as exported, optionally with `#[js_export(rename = "...")]`.
+#[proc_macro_attribute]
+pub fn js_export(_args: TokenStream, input: TokenStream) -> TokenStream {
+    process_js_export(input.into()).into()
+}
@@ -1,100 +1,664 @@
-use core::panic;
-
 use darling::{
-    ast::{Data, Style},
-    FromDeriveInput, FromField,
+    ast::{Data, Fields, Style},
+    util::Override,
+    FromDeriveInput, FromField, FromVariant,
 };
 use proc_macro2::TokenStream;
-use quote::quote;
-use syn::DeriveInput;
+use quote::{format_ident, quote};
+use syn::{ext::IdentExt, DeriveInput};
+
+use crate::rename::RenameRule;
 
 #[derive(Debug, FromDeriveInput)]
-struct StructData {
-    ident: syn::Ident,
-    generics: syn::Generics,
-    data: Data<(), StructFields>,
+#[darling(attributes(js))]
+pub(crate) struct StructData {
+    pub(crate) ident: syn::Ident,
+    pub(crate) generics: syn::Generics,
+    pub(crate) data: Data<EnumVariant, StructFields>,
+    /// represent enums as internally tagged objects, e.g. `#[js(tag = "type")]`
+    #[darling(default)]
+    pub(crate) tag: Option<String>,
+    /// rename struct fields or enum variants, e.g. `#[js(rename_all = "camelCase")]`
+    #[darling(default)]
+    pub(crate) rename_all: Option<RenameRule>,
 }
 
 #[derive(Debug, FromField)]
-struct StructFields {
-    ident: Option<syn::Ident>,
-    ty: syn::Type,
+#[darling(attributes(js))]
+pub(crate) struct StructFields {
+    pub(crate) ident: Option<syn::Ident>,
+    pub(crate) ty: syn::Type,
+    #[darling(default)]
+    rename: Option<String>,
+    /// never converted, filled with its default value
+    #[darling(default)]
+    pub(crate) skip: bool,
+    /// `#[js(default)]` or `#[js(default = "path")]` when the property is missing
+    #[darling(default)]
+    pub(crate) default: Option<Override<syn::Path>>,
+    /// merge the properties of the field into its parent object
+    #[darling(default)]
+    pub(crate) flatten: bool,
+}
+
+#[derive(Debug, FromVariant)]
+#[darling(attributes(js))]
+pub(crate) struct EnumVariant {
+    pub(crate) ident: syn::Ident,
+    pub(crate) fields: Fields<StructFields>,
+    #[darling(default)]
+    rename: Option<String>,
+}
+
+impl StructFields {
+    fn name(&self) -> &syn::Ident {
+        self.ident.as_ref().expect("Field must have a name")
+    }
+
+    /// the property name on the js side
+    pub(crate) fn js_name(&self, rule: Option<RenameRule>) -> String {
+        if let Some(rename) = &self.rename {
+            return rename.clone();
+        }
+        let name = self.name().unraw().to_string();
+        match rule {
+            Some(rule) => rule.apply_to_field(&name),
+            None => name,
+        }
+    }
+
+    fn default_value(&self) -> TokenStream {
+        match &self.default {
+            Some(Override::Explicit(path)) => quote! { #path() },
+            _ => quote! { ::core::default::Default::default() },
+        }
+    }
+}
+
+impl EnumVariant {
+    pub(crate) fn js_name(&self, rule: Option<RenameRule>) -> String {
+        if let Some(rename) = &self.rename {
+            return rename.clone();
+        }
+        let name = self.ident.unraw().to_string();
+        match rule {
+            Some(rule) => rule.apply_to_variant(&name),
+            None => name,
+        }
+    }
 }
 
 pub(crate) fn process_from_js(input: DeriveInput) -> TokenStream {
-    let (ident, generics, merged, fields) = parse_struct(input);
+    expand_from_js(input).unwrap_or_else(|e| e.write_errors())
+}
 
-    let code = fields.iter().map(|field| {
-        let name = field.ident.as_ref().expect("Field must have a name");
-        let ty = &field.ty;
+pub(crate) fn process_into_js(input: DeriveInput) -> TokenStream {
+    expand_into_js(input).unwrap_or_else(|e| e.write_errors())
+}
 
-        quote! {
-            let #name: #ty = obj.get(stringify!(#name))?;
+fn expand_from_js(input: DeriveInput) -> darling::Result<TokenStream> {
+    let data = StructData::from_derive_input(&input)?;
+    data.validate()?;
+    let ident = &data.ident;
+    let merged = data.merged_generics();
+    let (impl_generics, _, _) = merged.split_for_impl();
+    let (_, ty_generics, where_clause) = data.generics.split_for_impl();
+    let body = match &data.data {
+        Data::Struct(fields) => match fields.style {
+            Style::Struct => from_js_struct(ident, &fields.fields, data.rename_all),
+            Style::Tuple if fields.len() == 1 => quote! {
+                Ok(Self(rquickjs::FromJs::from_js(ctx, v)?))
+            },
+            Style::Tuple => from_js_tuple_struct(ident, fields.len()),
+            Style::Unit => quote! {
+                if v.is_null() || v.is_undefined() {
+                    Ok(Self)
+                } else {
+                    Err(rquickjs::Error::new_from_js(v.type_name(), stringify!(#ident)))
+                }
+            },
+        },
+        Data::Enum(variants) => match &data.tag {
+            Some(tag) => from_js_internally_tagged(ident, variants, tag, data.rename_all),
+            None => from_js_externally_tagged(ident, variants, data.rename_all),
+        },
+    };
+
+    Ok(quote! {
+        impl #impl_generics rquickjs::FromJs<'js> for #ident #ty_generics #where_clause {
+            fn from_js(ctx: &rquickjs::Ctx<'js>, v: rquickjs::Value<'js>) -> rquickjs::Result<Self> {
+                #[allow(dead_code)]
+                fn from_js_field<'a, T: rquickjs::FromJs<'a>>(
+                    ctx: &rquickjs::Ctx<'a>,
+                    obj: &rquickjs::Object<'a>,
+                    key: &str,
+                    to: &'static str,
+                ) -> rquickjs::Result<T> {
+                    let value: rquickjs::Value<'a> = obj.get(key)?;
+                    let from = value.type_name();
+                    let missing = value.is_undefined();
+                    T::from_js(ctx, value).map_err(|err| match err {
+                        rquickjs::Error::FromJs { .. } if missing => {
+                            rquickjs::Error::new_from_js_message(from, to, format!("missing field `{key}`"))
+                        }
+                        rquickjs::Error::FromJs { to: expected, .. } => {
+                            rquickjs::Error::new_from_js_message(
+                                from,
+                                to,
+                                format!("invalid field `{key}`, expected {expected}"),
+                            )
+                        }
+                        err => err,
+                    })
+                }
+
+                let _ = ctx;
+                #body
+            }
+        }
+    })
+}
+
+fn expand_into_js(input: DeriveInput) -> darling::Result<TokenStream> {
+    let data = StructData::from_derive_input(&input)?;
+    data.validate()?;
+    let ident = &data.ident;
+    let merged = data.merged_generics();
+    let (impl_generics, _, _) = merged.split_for_impl();
+    let (_, ty_generics, where_clause) = data.generics.split_for_impl();
+    let body = match &data.data {
+        Data::Struct(fields) => match fields.style {
+            Style::Struct => into_js_struct(&fields.fields, data.rename_all),
+            Style::Tuple if fields.len() == 1 => quote! {
+                rquickjs::IntoJs::into_js(self.0, ctx)
+            },
+            Style::Tuple => into_js_tuple_struct(fields.len()),
+            Style::Unit => quote! {
+                Ok(rquickjs::Value::new_null(ctx.clone()))
+            },
+        },
+        Data::Enum(variants) => match &data.tag {
+            Some(tag) => into_js_internally_tagged(variants, tag, data.rename_all),
+            None => into_js_externally_tagged(variants, data.rename_all),
+        },
+    };
+
+    Ok(quote! {
+        impl #impl_generics rquickjs::IntoJs<'js> for #ident #ty_generics #where_clause {
+            fn into_js(self, ctx: &rquickjs::Ctx<'js>) -> rquickjs::Result<rquickjs::Value<'js>> {
+                #body
+            }
+        }
+    })
+}
+
+impl StructData {
+    /// report attribute misuse as compile errors pointing at the offending item
+    pub(crate) fn validate(&self) -> darling::Result<()> {
+        let mut errors = darling::Error::accumulator();
+        match &self.data {
+            Data::Struct(fields) => {
+                if self.tag.is_some() {
+                    errors.push(
+                        darling::Error::custom("`tag` is only supported on enums")
+                            .with_span(&self.ident),
+                    );
+                }
+                validate_fields(fields, &mut errors);
+            }
+            Data::Enum(variants) => {
+                for variant in variants {
+                    if self.tag.is_some()
+                        && variant.fields.style == Style::Tuple
+                        && variant.fields.len() != 1
+                    {
+                        errors.push(
+                            darling::Error::custom(
+                                "tuple variants are not supported by internally tagged enums",
+                            )
+                            .with_span(&variant.ident),
+                        );
+                    }
+                    validate_fields(&variant.fields, &mut errors);
+                }
+            }
+        }
+        errors.finish()
+    }
+
+    fn merged_generics(&self) -> syn::Generics {
+        let mut merged = self.generics.clone();
+        merged.params.push(syn::parse_quote!('js));
+        merged
+    }
+}
+
+fn validate_fields(fields: &Fields<StructFields>, errors: &mut darling::error::Accumulator) {
+    for field in fields.iter() {
+        let span = field
+            .ident
+            .as_ref()
+            .map(|ident| ident.span())
+            .unwrap_or_else(|| syn::spanned::Spanned::span(&field.ty));
+        if fields.style == Style::Tuple
+            && (field.rename.is_some() || field.flatten || field.skip || field.default.is_some())
+        {
+            errors.push(
+                darling::Error::custom("field attributes are not supported on unnamed fields")
+                    .with_span(&span),
+            );
+        }
+        if field.skip && field.flatten {
+            errors.push(
+                darling::Error::custom("`skip` and `flatten` can not be used together")
+                    .with_span(&span),
+            );
+        }
+    }
+}
+
+/// bind the object in `value` to `obj`, returning a conversion error for anything else
+fn expect_object(value: TokenStream, to: &syn::Ident) -> TokenStream {
+    quote! {
+        let from = #value.type_name();
+        let Some(obj) = #value.into_object() else {
+            return Err(rquickjs::Error::new_from_js_message(
+                from,
+                stringify!(#to),
+                "expected an object",
+            ));
+        };
+    }
+}
+
+fn from_js_struct(
+    ident: &syn::Ident,
+    fields: &[StructFields],
+    rule: Option<RenameRule>,
+) -> TokenStream {
+    let obj = expect_object(quote! { v }, ident);
+    let fields = get_fields(ident, fields, rule);
+
+    quote! {
+        #obj
+
+        Ok(#ident {
+            #(#fields),*
+        })
+    }
+}
+
+fn from_js_tuple_struct(ident: &syn::Ident, len: usize) -> TokenStream {
+    let fields = (0..len).map(|i| quote! { arr.get(#i)? });
+
+    quote! {
+        let from = v.type_name();
+        let Some(arr) = v.into_array() else {
+            return Err(rquickjs::Error::new_from_js_message(
+                from,
+                stringify!(#ident),
+                "expected an array",
+            ));
+        };
+        if arr.len() != #len {
+            return Err(rquickjs::Error::new_from_js_message(
+                "array",
+                stringify!(#ident),
+                format!("expected {} elements, found {}", #len, arr.len()),
+            ));
         }
+
+        Ok(Self(#(#fields),*))
+    }
+}
+
+fn into_js_tuple_struct(len: usize) -> TokenStream {
+    let code = (0..len).map(|i| {
+        let index = syn::Index::from(i);
+        quote! { arr.set(#i, self.#index)?; }
     });
 
-    let idents = fields.iter().map(|field| {
-        let name = field.ident.as_ref().expect("Field must have a name");
-        quote! { #name }
+    quote! {
+        let arr = rquickjs::Array::new(ctx.clone())?;
+
+        #(#code)*
+
+        Ok(arr.into_value())
+    }
+}
+
+fn into_js_struct(fields: &[StructFields], rule: Option<RenameRule>) -> TokenStream {
+    let code = set_fields(fields, rule, |field| {
+        let name = field.name();
+        quote! { self.#name }
     });
 
     quote! {
-        impl #merged rquickjs::FromJs<'js> for #ident #generics {
-            fn from_js(_ctx: &rquickjs::Ctx<'js>, v: rquickjs::Value<'js>) -> rquickjs::Result<Self> {
-                let obj = v.into_object().unwrap();
+        let obj = rquickjs::Object::new(ctx.clone())?;
 
-                #(#code)*
+        #code
+
+        Ok(obj.into())
+    }
+}
+
+/// statements setting the fields on `obj`, `value` yields the rust expression of each field
+fn set_fields(
+    fields: &[StructFields],
+    rule: Option<RenameRule>,
+    value: impl Fn(&StructFields) -> TokenStream,
+) -> TokenStream {
+    let code = fields.iter().filter(|field| !field.skip).map(|field| {
+        let value = value(field);
+        if field.flatten {
+            return quote! {
+                let value = rquickjs::IntoJs::into_js(#value, ctx)?;
+                if let Some(inner) = value.as_object() {
+                    for prop in inner.props::<rquickjs::Atom<'js>, rquickjs::Value<'js>>() {
+                        let (k, v) = prop?;
+                        obj.set(k, v)?;
+                    }
+                } else if !value.is_undefined() && !value.is_null() {
+                    return Err(rquickjs::Error::new_into_js(value.type_name(), "object"));
+                }
+            };
+        }
+        let key = field.js_name(rule);
+        quote! { obj.set(#key, #value)?; }
+    });
+
+    quote! { #(#code)* }
+}
 
-                Ok(#ident {
-                    #(#idents),*
-                })
+/// `field: expr` initializers reading the fields from `obj`, errors are reported against `to`
+fn get_fields(
+    to: &syn::Ident,
+    fields: &[StructFields],
+    rule: Option<RenameRule>,
+) -> Vec<TokenStream> {
+    fields
+        .iter()
+        .map(|field| {
+            let name = field.name();
+            let key = field.js_name(rule);
+            let ty = &field.ty;
+            let value = if field.skip {
+                field.default_value()
+            } else if field.flatten {
+                quote! { rquickjs::FromJs::from_js(ctx, obj.clone().into_value())? }
+            } else if field.default.is_some() {
+                let default = field.default_value();
+                quote! {{
+                    let value: rquickjs::Value<'js> = obj.get(#key)?;
+                    if value.is_undefined() {
+                        #default
+                    } else {
+                        from_js_field::<#ty>(ctx, &obj, #key, stringify!(#to))?
+                    }
+                }}
+            } else {
+                quote! { from_js_field::<#ty>(ctx, &obj, #key, stringify!(#to))? }
+            };
+            quote! { #name: #value }
+        })
+        .collect()
+}
+
+/// unit variants become strings, the others `{ Variant: payload }`
+fn into_js_externally_tagged(variants: &[EnumVariant], rule: Option<RenameRule>) -> TokenStream {
+    let arms = variants.iter().map(|variant| {
+        let name = variant.js_name(rule);
+        let pattern = variant_pattern(variant);
+        match variant.fields.style {
+            Style::Unit => quote! {
+                #pattern => rquickjs::IntoJs::into_js(#name, ctx),
+            },
+            _ => {
+                let payload = variant_payload(variant);
+                quote! {
+                    #pattern => {
+                        let payload: rquickjs::Value<'js> = { #payload };
+                        let obj = rquickjs::Object::new(ctx.clone())?;
+                        obj.set(#name, payload)?;
+                        Ok(obj.into())
+                    }
+                }
             }
         }
+    });
+
+    quote! {
+        match self {
+            #(#arms)*
+        }
     }
 }
 
-pub(crate) fn process_into_js(input: DeriveInput) -> TokenStream {
-    let (ident, generics, merged, fields) = parse_struct(input);
-    let code = fields.iter().map(|field| {
-        let name = field.ident.as_ref().expect("Field must have a name");
+fn from_js_externally_tagged(
+    ident: &syn::Ident,
+    variants: &[EnumVariant],
+    rule: Option<RenameRule>,
+) -> TokenStream {
+    let unit_arms = variants
+        .iter()
+        .filter(|variant| variant.fields.style == Style::Unit)
+        .map(|variant| {
+            let name = &variant.ident;
+            let key = variant.js_name(rule);
+            quote! { #key => return Ok(Self::#name), }
+        });
+
+    let arms = variants
+        .iter()
+        .filter(|variant| variant.fields.style != Style::Unit)
+        .map(|variant| {
+            let key = variant.js_name(rule);
+            let construct = variant_construct(ident, variant, quote! { payload });
+            quote! {
+                #key => {
+                    let payload: rquickjs::Value<'js> = obj.get(#key)?;
+                    return #construct;
+                }
+            }
+        });
 
+    quote! {
+        if let Some(s) = v.as_string() {
+            let tag = s.to_string()?;
+            match tag.as_str() {
+                #(#unit_arms)*
+                _ => {}
+            }
+        } else if let Some(obj) = v.as_object() {
+            let keys = obj.keys::<String>().collect::<rquickjs::Result<Vec<_>>>()?;
+            if let [key] = keys.as_slice() {
+                match key.as_str() {
+                    #(#arms)*
+                    _ => {}
+                }
+            }
+        }
+        Err(rquickjs::Error::new_from_js_message(
+            v.type_name(),
+            stringify!(#ident),
+            "unknown variant",
+        ))
+    }
+}
+
+/// `{ [tag]: "Variant", ...fields }`, tuple variants can not be represented this way
+fn into_js_internally_tagged(
+    variants: &[EnumVariant],
+    tag: &str,
+    rule: Option<RenameRule>,
+) -> TokenStream {
+    let arms = variants.iter().map(|variant| {
+        let name = &variant.ident;
+        let key = variant.js_name(rule);
+        let pattern = variant_pattern(variant);
+        let obj = match variant.fields.style {
+            Style::Unit => quote! { rquickjs::Object::new(ctx.clone())? },
+            Style::Struct => {
+                let code = set_fields(&variant.fields.fields, None, binding);
+                quote! {{
+                    let obj = rquickjs::Object::new(ctx.clone())?;
+                    #code
+                    obj
+                }}
+            }
+            // tuple variants with more fields are rejected by `validate`
+            Style::Tuple => quote! {
+                rquickjs::IntoJs::into_js(f0, ctx)?
+                    .into_object()
+                    .ok_or(rquickjs::Error::new_into_js(stringify!(#name), "object"))?
+            },
+        };
         quote! {
-            obj.set(stringify!(#name), self.#name)?;
+            #pattern => {
+                let obj = #obj;
+                obj.set(#tag, #key)?;
+                Ok(obj.into())
+            }
         }
     });
 
     quote! {
-        impl #merged rquickjs::IntoJs<'js> for #ident #generics {
-            fn into_js(self, ctx: &rquickjs::Ctx<'js>) -> rquickjs::Result<rquickjs::Value<'js>> {
-                let obj = rquickjs::Object::new(ctx.clone())?;
-
-                #(#code)*
+        match self {
+            #(#arms)*
+        }
+    }
+}
 
-                Ok(obj.into())
+fn from_js_internally_tagged(
+    ident: &syn::Ident,
+    variants: &[EnumVariant],
+    tag: &str,
+    rule: Option<RenameRule>,
+) -> TokenStream {
+    let arms = variants.iter().map(|variant| {
+        let name = &variant.ident;
+        let key = variant.js_name(rule);
+        let construct = match variant.fields.style {
+            Style::Unit => quote! { Ok(Self::#name) },
+            Style::Struct => {
+                let fields = get_fields(ident, &variant.fields.fields, None);
+                quote! { Ok(Self::#name { #(#fields),* }) }
             }
+            Style::Tuple => quote! {
+                Ok(Self::#name(rquickjs::FromJs::from_js(ctx, obj.into_value())?))
+            },
+        };
+        quote! { #key => #construct, }
+    });
+
+    let obj = expect_object(quote! { v }, ident);
+    quote! {
+        #obj
+        let tag: String = from_js_field(ctx, &obj, #tag, stringify!(#ident))?;
+        match tag.as_str() {
+            #(#arms)*
+            _ => Err(rquickjs::Error::new_from_js_message(
+                "object",
+                stringify!(#ident),
+                format!("unknown variant {tag}"),
+            )),
         }
     }
 }
 
-fn parse_struct(
-    input: DeriveInput,
-) -> (syn::Ident, syn::Generics, syn::Generics, Vec<StructFields>) {
-    let StructData {
-        ident,
-        generics,
-        data: Data::Struct(fields),
-    } = StructData::from_derive_input(&input).expect("Can not parse input")
-    else {
-        panic!("Only structs are supported");
-    };
-    let fields = match fields.style {
-        Style::Struct => fields.fields,
-        _ => panic!("Only named fields are supported"),
-    };
+/// the local a variant field is bound to by [`variant_pattern`]
+fn binding(field: &StructFields) -> TokenStream {
+    let ident = format_ident!("f_{}", field.name().unraw());
+    quote! { #ident }
+}
+
+/// `Self::Variant`, `Self::Variant(f0, f1)` or `Self::Variant { a: f_a, .. }`
+fn variant_pattern(variant: &EnumVariant) -> TokenStream {
+    let name = &variant.ident;
+    match variant.fields.style {
+        Style::Unit => quote! { Self::#name },
+        Style::Tuple => {
+            let idents = (0..variant.fields.len()).map(|i| format_ident!("f{}", i));
+            quote! { Self::#name(#(#idents),*) }
+        }
+        Style::Struct => {
+            let fields = variant
+                .fields
+                .iter()
+                .filter(|field| !field.skip)
+                .map(|field| {
+                    let name = field.name();
+                    let binding = binding(field);
+                    quote! { #name: #binding }
+                });
+            quote! { Self::#name { #(#fields,)* .. } }
+        }
+    }
+}
 
-    let mut merged = generics.clone();
-    merged.params.push(syn::parse_quote!('js));
-    (ident, generics, merged, fields)
+/// the js value carried by a non-unit variant, bound by [`variant_pattern`]
+fn variant_payload(variant: &EnumVariant) -> TokenStream {
+    match variant.fields.style {
+        Style::Tuple if variant.fields.len() == 1 => quote! {
+            rquickjs::IntoJs::into_js(f0, ctx)?
+        },
+        Style::Tuple => {
+            let code = (0..variant.fields.len()).map(|i| {
+                let field = format_ident!("f{}", i);
+                quote! { arr.set(#i, #field)?; }
+            });
+            quote! {
+                let arr = rquickjs::Array::new(ctx.clone())?;
+                #(#code)*
+                arr.into_value()
+            }
+        }
+        _ => {
+            let code = set_fields(&variant.fields.fields, None, binding);
+            quote! {
+                let obj = rquickjs::Object::new(ctx.clone())?;
+                #code
+                obj.into_value()
+            }
+        }
+    }
+}
+
+/// build the variant back from the js value produced by [`variant_payload`]
+fn variant_construct(
+    ident: &syn::Ident,
+    variant: &EnumVariant,
+    payload: TokenStream,
+) -> TokenStream {
+    let name = &variant.ident;
+    match variant.fields.style {
+        Style::Tuple if variant.fields.len() == 1 => quote! {
+            Ok(Self::#name(rquickjs::FromJs::from_js(ctx, #payload)?))
+        },
+        Style::Tuple => {
+            let len = variant.fields.len();
+            let fields = (0..len).map(|i| quote! { arr.get(#i)? });
+            quote! {{
+                let arr = rquickjs::Array::from_value(#payload)?;
+                if arr.len() != #len {
+                    return Err(rquickjs::Error::new_from_js_message(
+                        "array",
+                        stringify!(#ident),
+                        format!("expected {} elements, found {}", #len, arr.len()),
+                    ));
+                }
+                Ok(Self::#name(#(#fields),*))
+            }}
+        }
+        _ => {
+            let obj = expect_object(payload, ident);
+            let fields = get_fields(ident, &variant.fields.fields, None);
+            quote! {{
+                #obj
+                Ok(Self::#name { #(#fields),* })
+            }}
+        }
+    }
 }
 
 #[cfg(test)]
@@ -137,4 +701,54 @@ mod tests {
         let code = process_into_js(parsed);
         println!("{}", code);
     }
+
+    #[test]
+    fn process_enum_should_work() {
+        let input = r#"
+          #[derive(IntoJs, FromJs)]
+          #[js(tag = "kind")]
+          enum Event {
+            Ping,
+            Message { from: String, text: String },
+            Payload(Data),
+          }
+        "#;
+
+        let parsed: DeriveInput = syn::parse_str(input).unwrap();
+        let info = StructData::from_derive_input(&parsed).unwrap();
+        assert_eq!(info.tag.as_deref(), Some("kind"));
+        let code = process_into_js(parsed.clone()).to_string();
+        assert!(code.contains("\"kind\""));
+        let code = process_from_js(parsed).to_string();
+        assert!(code.contains("unknown variant"));
+    }
+
+    #[test]
+    fn misuse_should_be_compile_errors() {
+        let inputs = [
+            r#"
+              #[js(tag = "type")]
+              struct Request { method: String }
+            "#,
+            r#"
+              #[js(tag = "type")]
+              enum Shape { Line(i32, i32) }
+            "#,
+            r#"
+              struct Request { #[js(skip, flatten)] inner: Inner }
+            "#,
+            r#"
+              #[js(rename_all = "camel")]
+              struct Request { method: String }
+            "#,
+            r#"
+              union Number { i: i32, f: f32 }
+            "#,
+        ];
+        for input in inputs {
+            let parsed = syn::parse_str(input).unwrap();
+            let code = process_into_js(parsed).to_string();
+            assert!(code.contains("compile_error"), "{input}");
+        }
+    }
 }
@@ -0,0 +1,112 @@
+use darling::FromMeta;
+
+/// The case conversions accepted by `#[js(rename_all = "...")]`, named as in serde.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl FromMeta for RenameRule {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        Ok(match value {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => return Err(darling::Error::unknown_value(value)),
+        })
+    }
+}
+
+impl RenameRule {
+    /// rename a `snake_case` field
+    pub(crate) fn apply_to_field(self, field: &str) -> String {
+        match self {
+            Self::Lower | Self::Snake => field.to_string(),
+            Self::Upper | Self::ScreamingSnake => field.to_ascii_uppercase(),
+            Self::Pascal => field.split('_').map(capitalize).collect(),
+            Self::Camel => {
+                let pascal = Self::Pascal.apply_to_field(field);
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(c) => c.to_lowercase().chain(chars).collect(),
+                    None => pascal,
+                }
+            }
+            Self::Kebab => field.replace('_', "-"),
+            Self::ScreamingKebab => field.replace('_', "-").to_ascii_uppercase(),
+        }
+    }
+
+    /// rename a `PascalCase` variant
+    pub(crate) fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            Self::Pascal => variant.to_string(),
+            Self::Lower => variant.to_ascii_lowercase(),
+            Self::Upper => variant.to_ascii_uppercase(),
+            _ => {
+                let mut snake = String::new();
+                for (i, c) in variant.char_indices() {
+                    if i > 0 && c.is_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(c.to_ascii_lowercase());
+                }
+                self.apply_to_field(&snake)
+            }
+        }
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_field_should_work() {
+        let cases = [
+            (RenameRule::Camel, "content_type", "contentType"),
+            (RenameRule::Pascal, "content_type", "ContentType"),
+            (RenameRule::Kebab, "content_type", "content-type"),
+            (RenameRule::ScreamingSnake, "content_type", "CONTENT_TYPE"),
+            (RenameRule::ScreamingKebab, "content_type", "CONTENT-TYPE"),
+            (RenameRule::Camel, "body", "body"),
+        ];
+        for (rule, field, expected) in cases {
+            assert_eq!(rule.apply_to_field(field), expected);
+        }
+    }
+
+    #[test]
+    fn rename_variant_should_work() {
+        let cases = [
+            (RenameRule::Camel, "NotFound", "notFound"),
+            (RenameRule::Snake, "NotFound", "not_found"),
+            (RenameRule::Kebab, "NotFound", "not-found"),
+            (RenameRule::Lower, "NotFound", "notfound"),
+            (RenameRule::ScreamingSnake, "NotFound", "NOT_FOUND"),
+        ];
+        for (rule, variant, expected) in cases {
+            assert_eq!(rule.apply_to_variant(variant), expected);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use darling::{
+    ast::{Data, Fields, Style},
+    FromDeriveInput,
+};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, GenericArgument, PathArguments, Type};
+
+use crate::{
+    process_js::{EnumVariant, StructData, StructFields},
+    rename::RenameRule,
+};
+
+pub(crate) fn process_typescript(input: DeriveInput) -> TokenStream {
+    expand_typescript(input).unwrap_or_else(|e| e.write_errors())
+}
+
+fn expand_typescript(input: DeriveInput) -> darling::Result<TokenStream> {
+    let data = StructData::from_derive_input(&input)?;
+    data.validate()?;
+    let ident = &data.ident;
+    let (impl_generics, ty_generics, where_clause) = data.generics.split_for_impl();
+    let declaration = declaration(&data);
+
+    Ok(quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// TypeScript declaration of the shape this type takes in js
+            pub const TS_DECLARATION: &'static str = #declaration;
+        }
+    })
+}
+
+/// an `interface` for structs with named fields, a `type` alias for everything else
+fn declaration(data: &StructData) -> String {
+    let name = format!("{}{}", data.ident, type_params(&data.generics));
+    match &data.data {
+        Data::Struct(fields) => match fields.style {
+            Style::Struct => {
+                let extends: Vec<_> = fields
+                    .iter()
+                    .filter(|field| field.flatten && !field.skip)
+                    .map(|field| ts_type(&field.ty))
+                    .collect();
+                let extends = if extends.is_empty() {
+                    String::new()
+                } else {
+                    format!(" extends {}", extends.join(", "))
+                };
+                let members = members(&fields.fields, data.rename_all, "  ", ";\n");
+                format!("interface {name}{extends} {{\n{members}}}\n")
+            }
+            Style::Tuple => format!("type {name} = {};\n", tuple_type(fields)),
+            Style::Unit => format!("type {name} = null;\n"),
+        },
+        Data::Enum(variants) => {
+            let variants: String = variants
+                .iter()
+                .map(|variant| format!("\n  | {}", variant_type(variant, data)))
+                .collect();
+            format!("type {name} ={variants};\n")
+        }
+    }
+}
+
+fn variant_type(variant: &EnumVariant, data: &StructData) -> String {
+    let name = variant.js_name(data.rename_all);
+    match &data.tag {
+        None => match variant.fields.style {
+            Style::Unit => format!("{name:?}"),
+            Style::Tuple => format!("{{ {name:?}: {} }}", tuple_type(&variant.fields)),
+            Style::Struct => format!("{{ {name:?}: {} }}", object_type(&variant.fields)),
+        },
+        Some(tag) => {
+            let tagged = format!("{{ {tag:?}: {name:?} }}");
+            match variant.fields.style {
+                Style::Unit => tagged,
+                Style::Tuple => format!("{tagged} & {}", tuple_type(&variant.fields)),
+                Style::Struct => format!("{tagged} & {}", object_type(&variant.fields)),
+            }
+        }
+    }
+}
+
+/// `T` for newtypes, `[A, B]` for tuples
+fn tuple_type(fields: &Fields<StructFields>) -> String {
+    let types: Vec<_> = fields.iter().map(|field| ts_type(&field.ty)).collect();
+    match types.as_slice() {
+        [ty] => ty.clone(),
+        _ => format!("[{}]", types.join(", ")),
+    }
+}
+
+fn object_type(fields: &Fields<StructFields>) -> String {
+    let mut ty = format!("{{ {}}}", members(&fields.fields, None, "", "; "));
+    for field in fields.iter().filter(|field| field.flatten && !field.skip) {
+        ty = format!("{ty} & {}", ts_type(&field.ty));
+    }
+    ty
+}
+
+fn members(fields: &[StructFields], rule: Option<RenameRule>, indent: &str, end: &str) -> String {
+    fields
+        .iter()
+        .filter(|field| !field.skip && !field.flatten)
+        .map(|field| {
+            let key = field.js_name(rule);
+            let optional = if field.default.is_some() || option_inner(&field.ty).is_some() {
+                "?"
+            } else {
+                ""
+            };
+            format!(
+                "{indent}{}{optional}: {}{end}",
+                property(&key),
+                ts_type(&field.ty)
+            )
+        })
+        .collect()
+}
+
+/// quote property names which are not valid identifiers
+fn property(key: &str) -> String {
+    let valid = key.chars().enumerate().all(|(i, c)| {
+        c == '_' || c == '$' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+    });
+    if valid && !key.is_empty() {
+        key.to_string()
+    } else {
+        format!("{key:?}")
+    }
+}
+
+fn type_params(generics: &syn::Generics) -> String {
+    let params: Vec<_> = generics
+        .type_params()
+        .map(|p| p.ident.to_string())
+        .collect();
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", params.join(", "))
+    }
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Path(path) => {
+            let segment = path.path.segments.last()?;
+            if segment.ident != "Option" {
+                return None;
+            }
+            generic_args(&segment.arguments).into_iter().next()
+        }
+        _ => None,
+    }
+}
+
+fn generic_args(args: &PathArguments) -> Vec<&Type> {
+    match args {
+        PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// map a rust type to the TypeScript type of its js representation
+pub(crate) fn ts_type(ty: &Type) -> String {
+    match ty {
+        Type::Path(path) => {
+            let Some(segment) = path.path.segments.last() else {
+                return "unknown".to_string();
+            };
+            let args = generic_args(&segment.arguments);
+            let arg = |i: usize| {
+                args.get(i)
+                    .map(|ty| ts_type(ty))
+                    .unwrap_or("unknown".into())
+            };
+            match segment.ident.to_string().as_str() {
+                "String" | "str" | "char" | "PathBuf" | "Path" => "string".to_string(),
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64"
+                | "u128" | "usize" | "f32" | "f64" => "number".to_string(),
+                "bool" => "boolean".to_string(),
+                "Option" => format!("{} | null", arg(0)),
+                "Vec" | "VecDeque" | "HashSet" | "BTreeSet" | "IndexSet" => array(&arg(0)),
+                "HashMap" | "BTreeMap" | "IndexMap" => format!("Record<{}, {}>", arg(0), arg(1)),
+                "Box" | "Rc" | "Arc" | "Cow" => arg(args.len().saturating_sub(1)),
+                "Result" => arg(0),
+                "Value" => "unknown".to_string(),
+                name if args.is_empty() => name.to_string(),
+                name => {
+                    let args: Vec<_> = args.iter().map(|ty| ts_type(ty)).collect();
+                    format!("{name}<{}>", args.join(", "))
+                }
+            }
+        }
+        Type::Reference(r) => ts_type(&r.elem),
+        Type::Paren(p) => ts_type(&p.elem),
+        Type::Group(g) => ts_type(&g.elem),
+        Type::Array(a) => array(&ts_type(&a.elem)),
+        Type::Slice(s) => array(&ts_type(&s.elem)),
+        Type::Tuple(t) if t.elems.is_empty() => "null".to_string(),
+        Type::Tuple(t) => {
+            let elems: Vec<_> = t.elems.iter().map(ts_type).collect();
+            format!("[{}]", elems.join(", "))
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+fn array(elem: &str) -> String {
+    if elem.contains(' ') {
+        format!("({elem})[]")
+    } else {
+        format!("{elem}[]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declare(input: &str) -> String {
+        let parsed = syn::parse_str(input).unwrap();
+        let data = StructData::from_derive_input(&parsed).unwrap();
+        declaration(&data)
+    }
+
+    #[test]
+    fn ts_type_should_work() {
+        let cases = [
+            ("String", "string"),
+            ("u16", "number"),
+            ("Option<String>", "string | null"),
+            ("Vec<Option<u8>>", "(number | null)[]"),
+            ("HashMap<String, Vec<String>>", "Record<string, string[]>"),
+            ("(u8, bool)", "[number, boolean]"),
+            ("Arc<Point>", "Point"),
+        ];
+        for (ty, expected) in cases {
+            let ty: Type = syn::parse_str(ty).unwrap();
+            assert_eq!(ts_type(&ty), expected);
+        }
+    }
+
+    #[test]
+    fn struct_declaration_should_work() {
+        let code = declare(
+            r#"
+              #[js(rename_all = "camelCase")]
+              struct Request {
+                method: String,
+                signed_cookies: HashMap<String, String>,
+                body: Option<String>,
+                #[js(skip)]
+                raw: Vec<u8>,
+                #[js(rename = "x-id", default)]
+                id: u32,
+                #[js(flatten)]
+                paging: Paging,
+              }
+            "#,
+        );
+        assert_eq!(
+            code,
+            "interface Request extends Paging {\n  method: string;\n  signedCookies: Record<string, string>;\n  body?: string | null;\n  \"x-id\"?: number;\n}\n"
+        );
+    }
+
+    #[test]
+    fn enum_declaration_should_work() {
+        let code = declare(
+            r#"
+              enum Shape {
+                Empty,
+                Line(i32, i32),
+                Rect { width: i32, height: i32 },
+              }
+            "#,
+        );
+        assert_eq!(
+            code,
+            "type Shape =\n  | \"Empty\"\n  | { \"Line\": [number, number] }\n  | { \"Rect\": { width: number; height: number; } };\n"
+        );
+
+        let code = declare(
+            r#"
+              #[js(tag = "kind")]
+              enum Event {
+                Ping,
+                Message { text: String },
+              }
+            "#,
+        );
+        assert_eq!(
+            code,
+            "type Event =\n  | { \"kind\": \"Ping\" }\n  | { \"kind\": \"Message\" } & { text: string; };\n"
+        );
+    }
+}
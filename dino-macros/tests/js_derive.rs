@@ -0,0 +1,282 @@
+use dino_macros::{FromJs, IntoJs};
+use rquickjs::{Context, Ctx, FromJs, IntoJs, Runtime};
+
+#[derive(Debug, Clone, PartialEq, IntoJs, FromJs)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Line(i32, i32),
+    Rect { width: i32, height: i32 },
+}
+
+#[derive(Debug, Clone, PartialEq, IntoJs, FromJs)]
+#[js(tag = "kind")]
+enum Event {
+    Ping,
+    Message { from: String, text: String },
+}
+
+fn with_ctx(f: impl FnOnce(Ctx) -> rquickjs::Result<()>) {
+    let rt = Runtime::new().unwrap();
+    let ctx = Context::full(&rt).unwrap();
+    ctx.with(f).unwrap();
+}
+
+fn to_json<'js, T: IntoJs<'js>>(ctx: &Ctx<'js>, v: T) -> rquickjs::Result<String> {
+    let v = v.into_js(ctx)?;
+    ctx.json_stringify(v)?.unwrap().to_string()
+}
+
+#[test]
+fn externally_tagged_enum_should_roundtrip() {
+    with_ctx(|ctx| {
+        let cases = [
+            (Shape::Empty, r#""Empty""#),
+            (Shape::Circle(1.5), r#"{"Circle":1.5}"#),
+            (Shape::Line(1, 2), r#"{"Line":[1,2]}"#),
+            (
+                Shape::Rect {
+                    width: 3,
+                    height: 4,
+                },
+                r#"{"Rect":{"width":3,"height":4}}"#,
+            ),
+        ];
+        for (shape, json) in cases {
+            assert_eq!(to_json(&ctx, shape.clone())?, json);
+            let v = ctx.json_parse(json)?;
+            assert_eq!(Shape::from_js(&ctx, v)?, shape);
+            let v = shape.clone().into_js(&ctx)?;
+            assert_eq!(Shape::from_js(&ctx, v)?, shape);
+        }
+
+        let v = ctx.json_parse(r#"{"Line":[5,6]}"#)?;
+        assert_eq!(Shape::from_js(&ctx, v)?, Shape::Line(5, 6));
+        let v = ctx.json_parse(r#""Unknown""#)?;
+        assert!(Shape::from_js(&ctx, v).is_err());
+        Ok(())
+    });
+}
+
+#[test]
+fn internally_tagged_enum_should_roundtrip() {
+    with_ctx(|ctx| {
+        assert_eq!(to_json(&ctx, Event::Ping)?, r#"{"kind":"Ping"}"#);
+        let msg = Event::Message {
+            from: "tyr".to_string(),
+            text: "hi".to_string(),
+        };
+        assert_eq!(
+            to_json(&ctx, msg.clone())?,
+            r#"{"from":"tyr","text":"hi","kind":"Message"}"#
+        );
+        for event in [Event::Ping, msg] {
+            let v = event.clone().into_js(&ctx)?;
+            assert_eq!(Event::from_js(&ctx, v)?, event);
+        }
+
+        let v = ctx.json_parse(r#"{"kind":"Message","from":"a","text":"b"}"#)?;
+        assert_eq!(
+            Event::from_js(&ctx, v)?,
+            Event::Message {
+                from: "a".to_string(),
+                text: "b".to_string()
+            }
+        );
+        let v = ctx.json_parse(r#"{"kind":"Pong"}"#)?;
+        assert!(Event::from_js(&ctx, v).is_err());
+        Ok(())
+    });
+}
+
+fn default_limit() -> u32 {
+    10
+}
+
+#[derive(Debug, Default, PartialEq, IntoJs, FromJs)]
+struct Paging {
+    page: u32,
+    #[js(default = "default_limit")]
+    page_size: u32,
+}
+
+#[derive(Debug, PartialEq, IntoJs, FromJs)]
+#[js(rename_all = "camelCase")]
+struct Query {
+    search_term: String,
+    #[js(rename = "type")]
+    kind: String,
+    #[js(skip)]
+    cached: bool,
+    #[js(default)]
+    include_deleted: bool,
+    #[js(flatten)]
+    paging: Paging,
+}
+
+#[derive(Debug, PartialEq, IntoJs, FromJs)]
+#[js(rename_all = "snake_case")]
+enum Status {
+    NotFound,
+    #[js(rename = "ok")]
+    Success,
+}
+
+#[test]
+fn field_attributes_should_work() {
+    with_ctx(|ctx| {
+        let query = Query {
+            search_term: "dino".to_string(),
+            kind: "doc".to_string(),
+            cached: true,
+            include_deleted: false,
+            paging: Paging {
+                page: 2,
+                page_size: 20,
+            },
+        };
+        assert_eq!(
+            to_json(&ctx, query)?,
+            r#"{"searchTerm":"dino","type":"doc","includeDeleted":false,"page":2,"page_size":20}"#
+        );
+
+        // a skipped field comes back as its default, the others as they were
+        let query = Query {
+            search_term: "dino".to_string(),
+            kind: "doc".to_string(),
+            cached: true,
+            include_deleted: true,
+            paging: Paging {
+                page: 2,
+                page_size: 20,
+            },
+        };
+        let v = query.into_js(&ctx)?;
+        assert_eq!(
+            Query::from_js(&ctx, v)?,
+            Query {
+                search_term: "dino".to_string(),
+                kind: "doc".to_string(),
+                cached: false,
+                include_deleted: true,
+                paging: Paging {
+                    page: 2,
+                    page_size: 20,
+                },
+            }
+        );
+
+        let v = ctx.json_parse(r#"{"searchTerm":"dino","type":"doc","page":1,"cached":true}"#)?;
+        assert_eq!(
+            Query::from_js(&ctx, v)?,
+            Query {
+                search_term: "dino".to_string(),
+                kind: "doc".to_string(),
+                cached: false,
+                include_deleted: false,
+                paging: Paging {
+                    page: 1,
+                    page_size: 10
+                },
+            }
+        );
+        Ok(())
+    });
+}
+
+#[test]
+fn renamed_variants_should_work() {
+    with_ctx(|ctx| {
+        assert_eq!(to_json(&ctx, Status::NotFound)?, r#""not_found""#);
+        assert_eq!(to_json(&ctx, Status::Success)?, r#""ok""#);
+        let v = ctx.json_parse(r#""ok""#)?;
+        assert_eq!(Status::from_js(&ctx, v)?, Status::Success);
+        let v = ctx.json_parse(r#""not_found""#)?;
+        assert_eq!(Status::from_js(&ctx, v)?, Status::NotFound);
+        let v = ctx.json_parse(r#""success""#)?;
+        assert!(Status::from_js(&ctx, v).is_err());
+        Ok(())
+    });
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, IntoJs, FromJs)]
+enum Keyword {
+    r#type,
+    r#match(u32),
+}
+
+#[test]
+fn raw_variants_should_drop_their_prefix() {
+    with_ctx(|ctx| {
+        assert_eq!(to_json(&ctx, Keyword::r#type)?, r#""type""#);
+        assert_eq!(to_json(&ctx, Keyword::r#match(1))?, r#"{"match":1}"#);
+        let v = ctx.json_parse(r#""type""#)?;
+        assert_eq!(Keyword::from_js(&ctx, v)?, Keyword::r#type);
+        let v = ctx.json_parse(r#"{"match":2}"#)?;
+        assert_eq!(Keyword::from_js(&ctx, v)?, Keyword::r#match(2));
+        Ok(())
+    });
+}
+
+#[derive(Debug, PartialEq, IntoJs, FromJs)]
+struct UserId(String);
+
+#[derive(Debug, PartialEq, IntoJs, FromJs)]
+struct Point(i32, i32);
+
+#[derive(Debug, PartialEq, IntoJs, FromJs)]
+struct User {
+    id: UserId,
+    name: String,
+    location: Option<Point>,
+}
+
+#[test]
+fn tuple_and_newtype_structs_should_work() {
+    with_ctx(|ctx| {
+        assert_eq!(to_json(&ctx, UserId("u1".to_string()))?, r#""u1""#);
+        assert_eq!(to_json(&ctx, Point(1, 2))?, "[1,2]");
+
+        let v = ctx.json_parse(r#"{"id":"u1","name":"tyr","location":[3,4]}"#)?;
+        let user = User {
+            id: UserId("u1".to_string()),
+            name: "tyr".to_string(),
+            location: Some(Point(3, 4)),
+        };
+        assert_eq!(User::from_js(&ctx, v)?, user);
+        let v = user.into_js(&ctx)?;
+        assert_eq!(
+            User::from_js(&ctx, v)?,
+            User {
+                id: UserId("u1".to_string()),
+                name: "tyr".to_string(),
+                location: Some(Point(3, 4)),
+            }
+        );
+
+        let v = ctx.json_parse("[1,2,3]")?;
+        assert!(Point::from_js(&ctx, v).is_err());
+        Ok(())
+    });
+}
+
+#[test]
+fn from_js_should_return_typed_errors() {
+    with_ctx(|ctx| {
+        let v = ctx.json_parse(r#""not an object""#)?;
+        let err = User::from_js(&ctx, v).unwrap_err();
+        assert!(err.is_from_js());
+        assert!(err.to_string().contains("User"));
+
+        let v = ctx.json_parse(r#"{"id":"u1"}"#)?;
+        let err = User::from_js(&ctx, v).unwrap_err();
+        assert!(err.is_from_js());
+        assert!(err.to_string().contains("missing field `name`"));
+
+        let v = ctx.json_parse(r#"{"id":"u1","name":1}"#)?;
+        let err = User::from_js(&ctx, v).unwrap_err();
+        assert!(err.to_string().contains("invalid field `name`"));
+        Ok(())
+    });
+}
@@ -0,0 +1,100 @@
+use dino_macros::js_module;
+use rquickjs::{async_with, AsyncContext, AsyncRuntime, CatchResultExt, Module, Promise};
+
+#[js_module(name = "utils")]
+mod utils {
+    use rquickjs::{class::Trace, prelude::Opt};
+
+    #[js_export]
+    pub fn greet(name: String, greeting: Opt<String>) -> String {
+        format!("{}, {name}!", greeting.0.unwrap_or_else(|| "hello".into()))
+    }
+
+    #[js_export(rename = "double")]
+    pub async fn double_later(value: i32) -> i32 {
+        tokio::task::yield_now().await;
+        value * 2
+    }
+
+    #[js_export]
+    #[derive(Trace)]
+    #[rquickjs::class]
+    pub struct Counter {
+        #[qjs(get)]
+        count: i32,
+    }
+
+    #[rquickjs::methods]
+    impl Counter {
+        #[qjs(constructor)]
+        pub fn new(start: Opt<i32>) -> Self {
+            Self {
+                count: start.0.unwrap_or_default(),
+            }
+        }
+
+        pub fn incr(&mut self) -> i32 {
+            self.count += 1;
+            self.count
+        }
+    }
+}
+
+async fn eval(code: &str) -> String {
+    let rt = AsyncRuntime::new().unwrap();
+    let ctx = AsyncContext::full(&rt).await.unwrap();
+    async_with!(ctx => |ctx| {
+        utils::JsModule::register(&ctx).unwrap();
+        let promise: Promise = ctx.eval(code).catch(&ctx).unwrap();
+        promise.into_future::<String>().await.catch(&ctx).unwrap()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn registered_module_should_expose_exports() {
+    let ret = eval(
+        r#"
+        (async () => {
+          const counter = new utils.Counter(5);
+          counter.incr();
+          const doubled = await utils.double(21);
+          return [utils.greet("dino"), utils.greet("dino", "hi"), doubled, counter.count].join(" ");
+        })()
+        "#,
+    )
+    .await;
+    assert_eq!(ret, "hello, dino! hi, dino! 42 6");
+}
+
+#[tokio::test]
+async fn module_should_be_importable() {
+    let rt = AsyncRuntime::new().unwrap();
+    let ctx = AsyncContext::full(&rt).await.unwrap();
+    let ret = async_with!(ctx => |ctx| {
+        Module::declare_def::<utils::JsModule, _>(ctx.clone(), "utils").unwrap();
+        let (_, promise) = Module::declare(
+            ctx.clone(),
+            "main",
+            "import { greet, double } from 'utils'; globalThis.ret = greet(String(await double(2)));",
+        )
+        .catch(&ctx)
+        .unwrap()
+        .eval()
+        .catch(&ctx)
+        .unwrap();
+        promise.into_future::<()>().await.catch(&ctx).unwrap();
+        ctx.globals().get::<_, String>("ret").unwrap()
+    })
+    .await;
+    assert_eq!(ret, "hello, 4!");
+}
+
+#[test]
+fn ts_declaration_should_describe_exports() {
+    assert_eq!(utils::JsModule::NAME, "utils");
+    assert_eq!(
+        utils::JsModule::TS_DECLARATION,
+        "declare const utils: {\n  greet(name: string, greeting?: string): string;\n  double(value: number): Promise<number>;\n  Counter: new (...args: any[]) => Counter;\n};\n"
+    );
+}
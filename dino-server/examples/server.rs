@@ -1,15 +1,47 @@
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use dino_macros::js_module;
+use dino_server::{
+    rquickjs::Ctx, DinoServer, Extension, Extensions, ProjectConfig, SwappableAppRouter,
+    TenentRouter,
+};
+use std::sync::Arc;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
 const TEST_CONF_STR: &str = r#"
 name: dino-test
+extensions:
+  - clock
 routes:
   /api/hello/{id}:
   - method: GET
     handler: hello
 "#;
 
+#[js_module]
+mod clock {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[js_export]
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+struct Clock;
+
+impl Extension for Clock {
+    fn name(&self) -> &str {
+        clock::JsModule::NAME
+    }
+
+    fn register<'js>(&self, ctx: &Ctx<'js>) -> dino_server::rquickjs::Result<()> {
+        clock::JsModule::register(ctx)
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let layer = Layer::new().with_filter(LevelFilter::INFO);
@@ -23,7 +55,7 @@ async fn main() -> anyhow::Result<()> {
                 headers:{
                     "content-type":"application/json"
                 },
-                body: JSON.stringify(req),
+                body: JSON.stringify({ ...req, now: clock.now() }),
             };
         }
         return{hello:hello};
@@ -31,10 +63,12 @@ async fn main() -> anyhow::Result<()> {
     "#;
 
     let config: ProjectConfig = serde_yaml::from_str(TEST_CONF_STR)?;
-    let routers = vec![TenentRouter::new(
-        "localhost",
-        SwappableAppRouter::try_new(code, config.routes)?,
-    )];
-    start_server(8888, routers).await?;
-    Ok(())
+    // the bundle is checked with the extensions the server registers
+    let extensions = Extensions::new([Arc::new(Clock) as Arc<dyn Extension>]);
+    let router = SwappableAppRouter::try_new_with(code, config, &extensions)?;
+    DinoServer::new(8888)
+        .extension(Clock)
+        .tenant(TenentRouter::new("localhost", router))
+        .serve()
+        .await
 }
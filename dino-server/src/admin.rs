@@ -0,0 +1,613 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{DefaultBodyLimit, Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AppError,
+    history::{unix_secs, DeployHistory},
+    AppRouter, AppState, ProjectConfig, StatsSnapshot, SwappableAppRouter, TenentRouter,
+};
+
+/// body size limit of deploys, whose bundles often exceed axum's default of 2 MB
+const DEPLOY_BODY_LIMIT: usize = 32 * 1024 * 1024;
+
+/// The admin api, deploying and removing tenants of a running server.
+#[derive(Clone)]
+struct Admin {
+    token: Arc<str>,
+    app: AppState,
+    history: Option<DeployHistory>,
+    /// changes derive the new tenants or versions from the current ones, so every handler
+    /// changing state makes them one at a time
+    lock: Arc<Mutex<()>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TenantInfo {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub prefix: String,
+    /// version being served
+    pub hash: String,
+    /// unix timestamp in seconds
+    pub deployed_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct VersionInfo {
+    pub hash: String,
+    /// unix timestamp in seconds
+    pub deployed_at: u64,
+    pub active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rollback {
+    hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct CanaryInfo {
+    pub stable: VersionStatsInfo,
+    pub canary: Option<VersionStatsInfo>,
+    /// percentage of the requests served by the canary
+    pub weight: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct VersionStatsInfo {
+    pub hash: String,
+    #[serde(flatten)]
+    pub stats: StatsSnapshot,
+}
+
+#[derive(Debug, Deserialize)]
+struct CanaryDeploy {
+    code: String,
+    /// the project config as yaml
+    config: String,
+    weight: u8,
+}
+
+/// a bundle with its config, as built by `dino build`
+#[derive(Debug, Deserialize)]
+struct Deploy {
+    code: String,
+    /// the project config as yaml
+    config: String,
+    /// hosts of the tenant, the existing ones or its name when left out
+    #[serde(default)]
+    hosts: Vec<String>,
+    #[serde(default)]
+    prefix: Option<String>,
+}
+
+/// `GET /tenants`, `PUT /tenants/:name`, `DELETE /tenants/:name`, `GET /tenants/:name/versions`,
+/// `POST /tenants/:name/rollback`, `GET|PUT|DELETE /tenants/:name/canary` and
+/// `POST /tenants/:name/canary/promote`, requests need an `Authorization: Bearer <token>` header
+pub(crate) fn router(token: String, app: AppState, history: Option<DeployHistory>) -> Router {
+    let admin = Admin {
+        token: token.into(),
+        app,
+        history,
+        lock: Default::default(),
+    };
+    Router::new()
+        .route("/tenants", get(list))
+        .route(
+            "/tenants/:name",
+            put(deploy)
+                .layer(DefaultBodyLimit::max(DEPLOY_BODY_LIMIT))
+                .delete(remove),
+        )
+        .route("/tenants/:name/versions", get(versions))
+        .route("/tenants/:name/rollback", post(rollback))
+        .route(
+            "/tenants/:name/canary",
+            put(deploy_canary)
+                .layer(DefaultBodyLimit::max(DEPLOY_BODY_LIMIT))
+                .get(canary)
+                .delete(abort_canary),
+        )
+        .route("/tenants/:name/canary/promote", post(promote_canary))
+        .with_state(admin)
+}
+
+async fn list(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<TenantInfo>>, AppError> {
+    admin.authorize(&headers)?;
+    let tenants = admin.app.tenants.load();
+    Ok(Json(
+        tenants.tenants().iter().map(TenantInfo::from).collect(),
+    ))
+}
+
+/// add a tenant, or swap the code of the tenant with the name while it keeps serving
+async fn deploy(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(deploy): Json<Deploy>,
+) -> Result<(StatusCode, Json<TenantInfo>), AppError> {
+    admin.authorize(&headers)?;
+    let router = admin.build(&name, &deploy.code, &deploy.config)?;
+    let loaded = router.load();
+
+    let _guard = admin.lock.lock().await;
+    let current = admin.app.tenants.load();
+    let mut tenants = current.tenants().to_vec();
+    let existing = tenants.iter().position(|t| t.router().load().name == name);
+    let (status, tenant) = match existing {
+        Some(i) if deploy.hosts.is_empty() && deploy.prefix.is_none() => {
+            (StatusCode::OK, tenants[i].clone())
+        }
+        Some(i) => {
+            tenants[i] = deploy.tenant(&name, tenants[i].router().clone());
+            (StatusCode::OK, tenants[i].clone())
+        }
+        None => {
+            tenants.push(deploy.tenant(&name, router.clone()));
+            (StatusCode::CREATED, tenants[tenants.len() - 1].clone())
+        }
+    };
+    let next = current
+        .with_tenants(tenants)
+        .map_err(AppError::InvalidTenant)?;
+    if let Some(history) = &admin.history {
+        history.record(&name, &loaded.hash, &deploy.code, &deploy.config)?;
+    }
+    if existing.is_some() {
+        tenant.router().replace(&router);
+    }
+    admin.app.tenants.swap(next);
+    Ok((status, Json(TenantInfo::from(&tenant))))
+}
+
+/// the versions kept in memory, followed by older ones of the deploy history
+async fn versions(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<VersionInfo>>, AppError> {
+    admin.authorize(&headers)?;
+    let tenant = admin.tenant(&name)?;
+    let mut versions: Vec<_> = tenant
+        .router()
+        .versions()
+        .iter()
+        .enumerate()
+        .map(|(i, v)| VersionInfo {
+            hash: v.hash.clone(),
+            deployed_at: unix_secs(v.deployed_at),
+            active: i == 0,
+        })
+        .collect();
+    if let Some(history) = &admin.history {
+        for v in history.versions(&name)? {
+            if !versions.iter().any(|known| known.hash == v.hash) {
+                versions.push(VersionInfo {
+                    hash: v.hash,
+                    deployed_at: v.deployed_at,
+                    active: false,
+                });
+            }
+        }
+    }
+    Ok(Json(versions))
+}
+
+/// serve a previous version again, rebuilding it from the deploy history when it is
+/// no longer in memory
+async fn rollback(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(rollback): Json<Rollback>,
+) -> Result<Json<TenantInfo>, AppError> {
+    admin.authorize(&headers)?;
+    let _guard = admin.lock.lock().await;
+    let tenant = admin.tenant(&name)?;
+    let router = tenant.router();
+    let in_memory = router.versions().iter().any(|v| v.hash == rollback.hash);
+    match &admin.history {
+        _ if in_memory => router.rollback(&rollback.hash)?,
+        Some(history) => {
+            let (code, config) = history
+                .load(&name, &rollback.hash)
+                .map_err(|_| AppError::VersionNotFound(rollback.hash.clone()))?;
+            let mut config = ProjectConfig::parse(&config)?;
+            config.name = name;
+            router.swap_with(code, config, &admin.app.extensions)?;
+        }
+        None => return Err(AppError::VersionNotFound(rollback.hash)),
+    }
+    Ok(Json(TenantInfo::from(&tenant)))
+}
+
+async fn remove(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    admin.authorize(&headers)?;
+    let _guard = admin.lock.lock().await;
+    let current = admin.app.tenants.load();
+    let mut tenants = current.tenants().to_vec();
+    tenants.retain(|t| t.router().load().name != name);
+    if tenants.len() == current.tenants().len() {
+        return Err(AppError::TenantNotFound(name));
+    }
+    let next = current.with_tenants(tenants)?;
+    admin.app.tenants.swap(next);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// the request counters of the stable version and the canary, if there is one
+async fn canary(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<CanaryInfo>, AppError> {
+    admin.authorize(&headers)?;
+    let tenant = admin.tenant(&name)?;
+    Ok(Json(CanaryInfo::from(tenant.router())))
+}
+
+/// serve a new version to a share of the requests, replacing the previous canary
+async fn deploy_canary(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(deploy): Json<CanaryDeploy>,
+) -> Result<Json<CanaryInfo>, AppError> {
+    admin.authorize(&headers)?;
+    let candidate = admin.build(&name, &deploy.code, &deploy.config)?;
+    let loaded = candidate.load();
+
+    let _guard = admin.lock.lock().await;
+    let tenant = admin.tenant(&name)?;
+    let router = tenant.router();
+    router
+        .set_canary(&candidate, deploy.weight)
+        .map_err(AppError::InvalidTenant)?;
+    if let Some(history) = &admin.history {
+        history.record(&name, &loaded.hash, &deploy.code, &deploy.config)?;
+    }
+    Ok(Json(CanaryInfo::from(router)))
+}
+
+async fn promote_canary(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<TenantInfo>, AppError> {
+    admin.authorize(&headers)?;
+    let _guard = admin.lock.lock().await;
+    let tenant = admin.tenant(&name)?;
+    tenant
+        .router()
+        .promote_canary()
+        .map_err(|_| AppError::CanaryNotFound(name))?;
+    Ok(Json(TenantInfo::from(&tenant)))
+}
+
+async fn abort_canary(
+    State(admin): State<Admin>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    admin.authorize(&headers)?;
+    let _guard = admin.lock.lock().await;
+    let tenant = admin.tenant(&name)?;
+    match tenant.router().abort_canary() {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(AppError::CanaryNotFound(name)),
+    }
+}
+
+impl Admin {
+    /// a router for an uploaded bundle, checked against the server's extensions and middlewares
+    fn build(&self, name: &str, code: &str, config: &str) -> Result<SwappableAppRouter, AppError> {
+        let mut config = ProjectConfig::parse(config).map_err(AppError::InvalidTenant)?;
+        config.name = name.to_string();
+        let router = SwappableAppRouter::try_new_with(code, config, &self.app.extensions)
+            .map_err(AppError::InvalidTenant)?;
+        self.app
+            .middlewares
+            .resolve(&router.load().middlewares)
+            .map_err(|e| AppError::InvalidTenant(e.into()))?;
+        Ok(router)
+    }
+
+    fn tenant(&self, name: &str) -> Result<TenentRouter, AppError> {
+        let tenants = self.app.tenants.load();
+        tenants
+            .tenants()
+            .iter()
+            .find(|t| t.router().load().name == name)
+            .cloned()
+            .ok_or_else(|| AppError::TenantNotFound(name.to_string()))
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), AppError> {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match token {
+            Some(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => Ok(()),
+            _ => Err(AppError::Unauthorized),
+        }
+    }
+}
+
+impl Deploy {
+    fn tenant(&self, name: &str, router: SwappableAppRouter) -> TenentRouter {
+        let mut hosts = self.hosts.iter();
+        let first = hosts.next().map_or(name, |host| host.as_str());
+        let tenant = hosts.fold(TenentRouter::new(first, router), |t, host| {
+            t.alias(host.clone())
+        });
+        match &self.prefix {
+            Some(prefix) => tenant.prefix(prefix.clone()),
+            None => tenant,
+        }
+    }
+}
+
+impl From<&TenentRouter> for TenantInfo {
+    fn from(tenant: &TenentRouter) -> Self {
+        let router = tenant.router().load();
+        Self {
+            name: router.name.clone(),
+            hosts: tenant.hosts().to_vec(),
+            prefix: tenant.path_prefix().to_string(),
+            hash: router.hash.clone(),
+            deployed_at: unix_secs(router.deployed_at),
+        }
+    }
+}
+
+impl From<&SwappableAppRouter> for CanaryInfo {
+    fn from(router: &SwappableAppRouter) -> Self {
+        let stats = |router: &AppRouter| VersionStatsInfo {
+            hash: router.hash.clone(),
+            stats: router.stats.snapshot(),
+        };
+        let canary = router.canary();
+        Self {
+            stable: stats(&router.load()),
+            canary: canary.as_ref().map(|c| stats(&c.router)),
+            weight: canary.map_or(0, |c| c.weight),
+        }
+    }
+}
+
+/// compare tokens without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Method, Request},
+        response::Response,
+    };
+    use serde_json::json;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::Tenants;
+
+    const CODE: &str = "(function(){ function hello(){} return { hello }; })();";
+    const CONFIG: &str =
+        "{ name: ignored, routes: { /api/hello: [{ method: GET, handler: hello }] } }";
+
+    async fn call(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Response {
+        let body = body.map_or(Body::empty(), |v| Body::from(v.to_string()));
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, "Bearer secret")
+            .header("content-type", "application/json")
+            .body(body)
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap()
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(app: &Router, uri: &str) -> T {
+        let res = call(app, Method::GET, uri, None).await;
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn list_tenants(app: &Router) -> Vec<TenantInfo> {
+        get_json(app, "/tenants").await
+    }
+
+    fn version(i: usize) -> serde_json::Value {
+        let code = CODE.replace("hello(){}", &format!("hello(){{ return {i}; }}"));
+        json!({ "code": code, "config": CONFIG })
+    }
+
+    #[tokio::test]
+    async fn admin_should_accept_large_bundles() {
+        let app = router("secret".into(), AppState::new(Tenants::default()), None);
+        let padding = format!("/* {} */", "x".repeat(4 * 1024 * 1024));
+        let deploy = json!({ "code": format!("{padding}{CODE}"), "config": CONFIG });
+        let res = call(&app, Method::PUT, "/tenants/billing", Some(deploy)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let code = "x".repeat(DEPLOY_BODY_LIMIT);
+        let deploy = json!({ "code": code, "config": CONFIG });
+        let res = call(&app, Method::PUT, "/tenants/billing", Some(deploy)).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn admin_should_deploy_and_remove_tenants() {
+        let state = AppState::new(Tenants::default());
+        let app = router("secret".to_string(), state.clone(), None);
+
+        let deploy = json!({ "code": CODE, "config": CONFIG, "hosts": ["billing.test"] });
+        let res = call(&app, Method::PUT, "/tenants/billing", Some(deploy)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let handle = state
+            .tenants
+            .load()
+            .get("billing.test", "/")
+            .unwrap()
+            .0
+            .clone();
+
+        let tenants = list_tenants(&app).await;
+        assert_eq!(tenants.len(), 1);
+        assert_eq!(tenants[0].name, "billing");
+        assert_eq!(tenants[0].hosts, vec!["billing.test".to_string()]);
+        let hash = tenants[0].hash.clone();
+
+        // the existing router is swapped in place and keeps its hosts
+        let code = CODE.replace("hello(){}", "hello(){ return 1; }");
+        let deploy = json!({ "code": code, "config": CONFIG });
+        let res = call(&app, Method::PUT, "/tenants/billing", Some(deploy)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let tenants = list_tenants(&app).await;
+        assert_eq!(tenants[0].hosts, vec!["billing.test".to_string()]);
+        assert_ne!(tenants[0].hash, hash);
+        assert_eq!(handle.load().hash, tenants[0].hash);
+
+        let res = call(&app, Method::DELETE, "/tenants/billing", None).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(list_tenants(&app).await.is_empty());
+        let res = call(&app, Method::DELETE, "/tenants/billing", None).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_should_roll_back_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = DeployHistory::new(dir.path());
+        let state = AppState::new(Tenants::default());
+        let app = router("secret".into(), state.clone(), Some(history.clone()));
+        for i in 1..=2 {
+            call(&app, Method::PUT, "/tenants/billing", Some(version(i))).await;
+        }
+        let versions: Vec<VersionInfo> = get_json(&app, "/tenants/billing/versions").await;
+        assert_eq!(versions.len(), 2);
+        assert!(versions[0].active && !versions[1].active);
+
+        let rollback = json!({ "hash": versions[1].hash });
+        let res = call(
+            &app,
+            Method::POST,
+            "/tenants/billing/rollback",
+            Some(rollback),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(list_tenants(&app).await[0].hash, versions[1].hash);
+
+        // after a restart the older versions come from the deploy history
+        let state = AppState::new(Tenants::default());
+        let app = router("secret".into(), state.clone(), Some(history));
+        call(&app, Method::PUT, "/tenants/billing", Some(version(3))).await;
+        let restarted: Vec<VersionInfo> = get_json(&app, "/tenants/billing/versions").await;
+        assert_eq!(restarted.len(), 3);
+        let rollback = json!({ "hash": versions[0].hash });
+        let res = call(
+            &app,
+            Method::POST,
+            "/tenants/billing/rollback",
+            Some(rollback),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(list_tenants(&app).await[0].hash, versions[0].hash);
+
+        let rollback = json!({ "hash": "unknown" });
+        let res = call(
+            &app,
+            Method::POST,
+            "/tenants/billing/rollback",
+            Some(rollback),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_should_manage_canaries() {
+        let state = AppState::new(Tenants::default());
+        let app = router("secret".into(), state.clone(), None);
+        call(&app, Method::PUT, "/tenants/billing", Some(version(1))).await;
+        let stable = state.tenants.load().tenants()[0].router().load();
+        stable
+            .stats
+            .record(StatusCode::OK, Duration::from_millis(10));
+
+        let mut canary = version(2);
+        canary["weight"] = 101.into();
+        let res = call(
+            &app,
+            Method::PUT,
+            "/tenants/billing/canary",
+            Some(canary.clone()),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        canary["weight"] = 5.into();
+        let res = call(&app, Method::PUT, "/tenants/billing/canary", Some(canary)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let info: CanaryInfo = get_json(&app, "/tenants/billing/canary").await;
+        assert_eq!(info.weight, 5);
+        assert_eq!(info.stable.stats.requests, 0);
+        let canary_hash = info.canary.unwrap().hash;
+
+        let res = call(&app, Method::POST, "/tenants/billing/canary/promote", None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(list_tenants(&app).await[0].hash, canary_hash);
+        let info: CanaryInfo = get_json(&app, "/tenants/billing/canary").await;
+        assert_eq!((info.canary, info.weight), (None, 0));
+
+        let res = call(&app, Method::DELETE, "/tenants/billing/canary", None).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_should_reject_invalid_requests() {
+        let app = router(
+            "secret".to_string(),
+            AppState::new(Tenants::default()),
+            None,
+        );
+        let req = Request::builder()
+            .uri("/tenants")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let config = CONFIG.replace("handler: hello", "handler: helo");
+        let deploy = json!({ "code": CODE, "config": config });
+        let res = call(&app, Method::PUT, "/tenants/billing", Some(deploy)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Body,
+    http::{
+        header::{
+            ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+            CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+            RANGE, VARY,
+        },
+        HeaderMap, Method, StatusCode,
+    },
+    response::Response,
+};
+use percent_encoding::percent_decode_str;
+use rquickjs::Ctx;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::extension::Extension;
+
+/// manifest written by `dino build` next to the packaged assets, mapping each url to its fingerprinted url
+pub const ASSETS_MANIFEST: &str = ".dino-assets.json";
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "no-cache";
+const INDEX_FILE: &str = "index.html";
+
+/// The public directory of a project, served before the routes are matched.
+#[derive(Debug)]
+pub struct PublicDir {
+    root: PathBuf,
+    manifest: HashMap<String, String>,
+    fingerprinted: HashSet<String>,
+}
+
+/// the variant of a file picked for a request
+struct Selected {
+    path: PathBuf,
+    len: u64,
+    encoding: Option<&'static str>,
+}
+
+impl PublicDir {
+    pub fn load(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        if !root.is_dir() {
+            anyhow::bail!("public directory {} does not exist", root.display());
+        }
+        // served files are checked to be below it once their symlinks are resolved
+        let root = fs::canonicalize(&root)?;
+        let manifest: HashMap<String, String> = match fs::read_to_string(root.join(ASSETS_MANIFEST))
+        {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(_) => HashMap::new(),
+        };
+        let fingerprinted = manifest.values().cloned().collect();
+        Ok(Self {
+            root,
+            manifest,
+            fingerprinted,
+        })
+    }
+
+    /// serve the file matching the request path, `None` to fall back to the routes
+    pub async fn serve(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Option<Response> {
+        if method != Method::GET && method != Method::HEAD {
+            return None;
+        }
+        let rel = sanitize(path)?;
+        let mut file = self.root.join(&rel);
+        let mut meta = tokio::fs::metadata(&file).await.ok()?;
+        if meta.is_dir() {
+            file = file.join(INDEX_FILE);
+            meta = tokio::fs::metadata(&file).await.ok()?;
+        }
+        if !meta.is_file() {
+            return None;
+        }
+        let real = resolve(&self.root, &file).await?;
+
+        let url = format!("/{}", rel.to_string_lossy().replace('\\', "/"));
+        let cache = if self.fingerprinted.contains(&url) {
+            IMMUTABLE
+        } else {
+            REVALIDATE
+        };
+        let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+        let etag = format!(
+            "\"{:x}-{:x}\"",
+            meta.len(),
+            modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        );
+        let content_type = mime_guess::from_path(&file).first_or_octet_stream();
+
+        let builder = Response::builder()
+            .header(CACHE_CONTROL, cache)
+            .header(LAST_MODIFIED, httpdate::fmt_http_date(modified))
+            .header(ACCEPT_RANGES, "bytes")
+            .header(VARY, "accept-encoding");
+        if not_modified(headers, &etag, modified) {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .header(ETAG, etag)
+                .body(Body::empty())
+                .ok();
+        }
+
+        let range = headers.get(RANGE).and_then(|v| v.to_str().ok());
+        let selected = match range {
+            Some(_) => Selected {
+                path: real,
+                len: meta.len(),
+                encoding: None,
+            },
+            None => select_encoding(&self.root, &file, real, meta.len(), headers).await,
+        };
+        let etag = match selected.encoding {
+            Some(encoding) => format!("{}-{encoding}\"", etag.trim_end_matches('"')),
+            None => etag,
+        };
+        let builder = builder
+            .header(CONTENT_TYPE, content_type.as_ref())
+            .header(ETAG, etag);
+        let builder = match selected.encoding {
+            Some(encoding) => builder.header(CONTENT_ENCODING, encoding),
+            None => builder,
+        };
+
+        let len = selected.len;
+        let (builder, start, count) = match range.map(|r| parse_range(r, len)) {
+            Some(Some(Ok((start, end)))) => (
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+                start,
+                end - start + 1,
+            ),
+            Some(Some(Err(()))) => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{len}"))
+                    .body(Body::empty())
+                    .ok();
+            }
+            _ => (builder.status(StatusCode::OK), 0, len),
+        };
+        let builder = builder.header(CONTENT_LENGTH, count);
+        if method == Method::HEAD {
+            return builder.body(Body::empty()).ok();
+        }
+        // stream only the bytes sent instead of reading the whole file
+        let mut content = tokio::fs::File::open(&selected.path).await.ok()?;
+        if start > 0 {
+            content.seek(SeekFrom::Start(start)).await.ok()?;
+        }
+        let body = Body::from_stream(ReaderStream::new(content.take(count)));
+        builder.body(body).ok()
+    }
+}
+
+/// expose the fingerprinted urls to js as the `assets` global
+impl Extension for PublicDir {
+    fn name(&self) -> &str {
+        "assets"
+    }
+
+    fn register<'js>(&self, ctx: &Ctx<'js>) -> rquickjs::Result<()> {
+        ctx.globals().set("assets", self.manifest.clone())
+    }
+}
+
+/// decode a request path into a relative file path, refusing traversal and hidden files
+fn sanitize(path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut rel = PathBuf::new();
+    for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+        if segment.starts_with('.') || segment.contains('\\') || Path::new(segment).has_root() {
+            return None;
+        }
+        rel.push(segment);
+    }
+    if decoded.ends_with('/') {
+        rel.push(INDEX_FILE);
+    }
+    Some(rel)
+}
+
+fn not_modified(headers: &HeaderMap, etag: &str, modified: SystemTime) -> bool {
+    if let Some(tags) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        // compressed variants share the validator of the file they were made from
+        let base = etag.trim_end_matches('"');
+        return tags.split(',').map(str::trim).any(|tag| {
+            let tag = tag.trim_start_matches("W/");
+            tag == "*"
+                || tag == etag
+                || tag
+                    .strip_prefix(base)
+                    .is_some_and(|rest| rest == "-br\"" || rest == "-gzip\"")
+        });
+    }
+    headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .and_then(|since| {
+            let modified = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+            let since = since.duration_since(UNIX_EPOCH).ok()?.as_secs();
+            Some(modified <= since)
+        })
+        .unwrap_or(false)
+}
+
+/// prefer a precompressed `.br` or `.gz` sibling the client accepts
+async fn select_encoding(
+    root: &Path,
+    file: &Path,
+    real: PathBuf,
+    len: u64,
+    headers: &HeaderMap,
+) -> Selected {
+    let accepted = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let accepts = |name: &str| {
+        accepted.split(',').any(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            parts.next() == Some(name) && parts.all(|p| p != "q=0")
+        })
+    };
+    for (encoding, ext) in [("br", "br"), ("gzip", "gz")] {
+        if !accepts(encoding) {
+            continue;
+        }
+        let mut name = file.as_os_str().to_owned();
+        name.push(".");
+        name.push(ext);
+        let Some(path) = resolve(root, Path::new(&name)).await else {
+            continue;
+        };
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            if meta.is_file() {
+                return Selected {
+                    path,
+                    len: meta.len(),
+                    encoding: Some(encoding),
+                };
+            }
+        }
+    }
+    Selected {
+        path: real,
+        len,
+        encoding: None,
+    }
+}
+
+/// the path of a file with its symlinks resolved, `None` when they lead out of the root
+async fn resolve(root: &Path, path: &Path) -> Option<PathBuf> {
+    let path = tokio::fs::canonicalize(path).await.ok()?;
+    path.starts_with(root).then_some(path)
+}
+
+/// parse a single `bytes=` range into inclusive bounds, `None` when the header should be ignored
+fn parse_range(range: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let bounds = match (start, end) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        (start, "") => (start.parse().ok()?, len.saturating_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(len.saturating_sub(1)))
+        }
+    };
+    if bounds.0 >= len {
+        return Some(Err(()));
+    }
+    Some(Ok(bounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    async fn body(res: Response) -> String {
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn public_dir() -> (tempfile::TempDir, PublicDir) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.css"), "body { color: red; }").unwrap();
+        fs::write(dir.path().join("app.css.br"), "brotli").unwrap();
+        fs::write(dir.path().join("app.1a2b3c4d.css"), "body { color: red; }").unwrap();
+        fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        fs::write(
+            dir.path().join(ASSETS_MANIFEST),
+            r#"{"/app.css": "/app.1a2b3c4d.css"}"#,
+        )
+        .unwrap();
+        let public = PublicDir::load(dir.path()).unwrap();
+        (dir, public)
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.parse().unwrap(), HeaderValue::from_static(v)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn serve_should_work() {
+        let (_dir, public) = public_dir();
+        let res = public
+            .serve(&Method::GET, "/app.css", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+        assert_eq!(res.headers()[CACHE_CONTROL], REVALIDATE);
+        assert_eq!(body(res).await, "body { color: red; }");
+
+        let res = public
+            .serve(&Method::GET, "/app.1a2b3c4d.css", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CACHE_CONTROL], IMMUTABLE);
+
+        let res = public
+            .serve(&Method::GET, "/docs/", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html");
+
+        let res = public
+            .serve(&Method::HEAD, "/app.css", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_LENGTH], "20");
+        assert_eq!(body(res).await, "");
+    }
+
+    #[tokio::test]
+    async fn serve_should_fall_back_to_routes() {
+        let (_dir, public) = public_dir();
+        let empty = HeaderMap::new();
+        for path in ["/api/hello", "/.env", "/../app.css", "/%2e%2e/app.css"] {
+            let res = public.serve(&Method::GET, path, &empty).await;
+            assert!(res.is_none(), "{path}");
+        }
+        assert!(public
+            .serve(&Method::POST, "/app.css", &empty)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn conditional_requests_should_work() {
+        let (_dir, public) = public_dir();
+        let res = public
+            .serve(&Method::GET, "/app.css", &HeaderMap::new())
+            .await
+            .unwrap();
+        let etag = res.headers()[ETAG].clone();
+        let last_modified = res.headers()[LAST_MODIFIED].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag);
+        let res = public
+            .serve(&Method::GET, "/app.css", &headers)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MODIFIED_SINCE, last_modified);
+        let res = public
+            .serve(&Method::GET, "/app.css", &headers)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_should_stay_in_the_public_dir() {
+        use std::os::unix::fs::symlink;
+
+        let (dir, public) = public_dir();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        fs::write(outside.path().join("app.css.gz"), "secret").unwrap();
+        symlink(
+            outside.path().join("secret.txt"),
+            dir.path().join("leak.txt"),
+        )
+        .unwrap();
+        symlink(outside.path(), dir.path().join("outside")).unwrap();
+        symlink(
+            outside.path().join("app.css.gz"),
+            dir.path().join("app.css.gz"),
+        )
+        .unwrap();
+        symlink(dir.path().join("app.css"), dir.path().join("style.css")).unwrap();
+
+        for path in ["/leak.txt", "/outside/secret.txt"] {
+            let res = public.serve(&Method::GET, path, &HeaderMap::new()).await;
+            assert!(res.is_none(), "{path}");
+        }
+        let res = public
+            .serve(
+                &Method::GET,
+                "/app.css",
+                &headers(&[("accept-encoding", "gzip")]),
+            )
+            .await
+            .unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body(res).await, "body { color: red; }");
+        // links within the directory are fine
+        let res = public
+            .serve(&Method::GET, "/style.css", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(body(res).await, "body { color: red; }");
+    }
+
+    #[tokio::test]
+    async fn range_and_encoding_should_work() {
+        let (_dir, public) = public_dir();
+        let res = public
+            .serve(
+                &Method::GET,
+                "/app.css",
+                &headers(&[("range", "bytes=0-3")]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 0-3/20");
+        assert_eq!(body(res).await, "body");
+
+        let res = public
+            .serve(
+                &Method::GET,
+                "/app.css",
+                &headers(&[("range", "bytes=7-11")]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(body(res).await, "color");
+
+        let res = public
+            .serve(
+                &Method::GET,
+                "/app.css",
+                &headers(&[("range", "bytes=100-")]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let res = public
+            .serve(
+                &Method::GET,
+                "/app.css",
+                &headers(&[("accept-encoding", "gzip, br")]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+        assert_eq!(body(res).await, "brotli");
+    }
+
+    #[test]
+    fn parse_range_should_work() {
+        assert_eq!(parse_range("bytes=0-9", 20), Some(Ok((0, 9))));
+        assert_eq!(parse_range("bytes=10-", 20), Some(Ok((10, 19))));
+        assert_eq!(parse_range("bytes=-5", 20), Some(Ok((15, 19))));
+        assert_eq!(parse_range("bytes=5-100", 20), Some(Ok((5, 19))));
+        assert_eq!(parse_range("bytes=30-40", 20), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,4-5", 20), None);
+        assert_eq!(parse_range("items=0-1", 20), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axum::http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{cookies::parse_cookies, router::AppRouter, VERSION_HEADER};
+
+/// requests with the same value go to the same version, for clients without cookies
+pub(crate) const STICKY_HEADER: &str = "x-dino-sticky";
+/// the version a client was assigned to
+pub(crate) const STICKY_COOKIE: &str = "dino-version";
+
+/// A new version serving a share of the requests until it is promoted.
+pub struct Canary {
+    pub router: AppRouter,
+    /// percentage of the requests, 0 to 100
+    pub weight: u8,
+}
+
+/// Request counters of a version, to decide whether a canary can be promoted.
+#[derive(Debug, Default)]
+pub struct VersionStats {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    latency_us: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatsSnapshot {
+    pub requests: u64,
+    /// responses with a 5xx status
+    pub errors: u64,
+    pub avg_latency_ms: f64,
+}
+
+impl VersionStats {
+    pub fn record(&self, status: StatusCode, elapsed: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_server_error() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let us = elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+        self.latency_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    /// start counting again, for the stable version next to a new canary
+    pub fn reset(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+        self.latency_us.store(0, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let latency_us = self.latency_us.load(Ordering::Relaxed);
+        StatsSnapshot {
+            requests,
+            errors: self.errors.load(Ordering::Relaxed),
+            avg_latency_ms: match requests {
+                0 => 0.0,
+                n => latency_us as f64 / n as f64 / 1000.0,
+            },
+        }
+    }
+}
+
+/// the version serving a request, and whether to pin the client to it with a cookie:
+/// the version named by the request's version header first, then the sticky cookie,
+/// then a bucket of the sticky header or a random one
+pub(crate) fn select(
+    stable: AppRouter,
+    canary: Option<&Canary>,
+    headers: &HeaderMap,
+) -> (AppRouter, bool) {
+    let Some(canary) = canary else {
+        return (stable, false);
+    };
+    let by_hash = |hash: &str| {
+        [&canary.router, &stable]
+            .into_iter()
+            .find(|router| router.hash == hash)
+            .cloned()
+    };
+    let forced = headers
+        .get(VERSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(by_hash);
+    let pinned = || {
+        parse_cookies(headers)
+            .get(STICKY_COOKIE)
+            .and_then(|v| by_hash(v))
+    };
+    if let Some(router) = forced.or_else(pinned) {
+        return (router, false);
+    }
+    let sticky = headers.get(STICKY_HEADER).map(|v| v.as_bytes());
+    let bucket = match sticky {
+        Some(key) => {
+            let hash = blake3::hash(key);
+            u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap()) % 100
+        }
+        None => (Uuid::new_v4().as_u128() % 100) as u64,
+    };
+    let router = match bucket < canary.weight as u64 {
+        true => canary.router.clone(),
+        false => stable,
+    };
+    (router, sticky.is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::COOKIE, HeaderValue};
+
+    use super::*;
+    use crate::{config::ProjectConfig, router::stub_bundle, SwappableAppRouter};
+
+    fn version(handler: &str) -> AppRouter {
+        let yaml =
+            format!("{{ name: p, routes: {{ /: [{{ method: GET, handler: {handler} }}] }} }}");
+        let config: ProjectConfig = serde_yaml::from_str(&yaml).unwrap();
+        SwappableAppRouter::try_new(stub_bundle(&config), config)
+            .unwrap()
+            .load()
+    }
+
+    #[test]
+    fn select_should_split_traffic() {
+        let stable = version("stable");
+        let canary = Canary {
+            router: version("canary"),
+            weight: 5,
+        };
+        let pick = |headers: &HeaderMap| {
+            let (router, pin) = select(stable.clone(), Some(&canary), headers);
+            (router.hash.clone(), pin)
+        };
+
+        let canary_hits = (0..2000)
+            .filter(|_| pick(&HeaderMap::new()).0 == canary.router.hash)
+            .count();
+        assert!((20..=200).contains(&canary_hits), "{canary_hits}");
+        assert!(pick(&HeaderMap::new()).1);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            VERSION_HEADER,
+            HeaderValue::from_str(&canary.router.hash).unwrap(),
+        );
+        assert_eq!(pick(&headers), (canary.router.hash.clone(), false));
+
+        let mut headers = HeaderMap::new();
+        let cookie = format!("{STICKY_COOKIE}={}", stable.hash);
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        assert_eq!(pick(&headers), (stable.hash.clone(), false));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(STICKY_HEADER, HeaderValue::from_static("user-42"));
+        let first = pick(&headers);
+        assert!(!first.1);
+        assert!((0..20).all(|_| pick(&headers) == first));
+
+        let (router, pin) = select(stable.clone(), None, &HeaderMap::new());
+        assert_eq!((router.hash.clone(), pin), (stable.hash.clone(), false));
+    }
+
+    #[test]
+    fn stats_should_work() {
+        let stats = VersionStats::default();
+        stats.record(StatusCode::OK, Duration::from_millis(10));
+        stats.record(StatusCode::BAD_GATEWAY, Duration::from_millis(30));
+        stats.record(StatusCode::NOT_FOUND, Duration::from_millis(20));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.requests, 3);
+        assert_eq!(snapshot.errors, 1);
+        assert_eq!(snapshot.avg_latency_ms, 20.0);
+        stats.reset();
+        assert_eq!(stats.snapshot().requests, 0);
+    }
+}
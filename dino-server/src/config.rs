@@ -1,47 +1,492 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use anyhow::{bail, Context as _};
 use axum::http::Method;
+use indexmap::IndexMap;
+use matchit::InsertError;
 use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
 
-pub type ProjectRoutes = HashMap<String, Vec<ProjectRoute>>;
+/// routes in the order of the config file
+pub type ProjectRoutes = IndexMap<String, Vec<ProjectRoute>>;
 
 #[derive(Debug, Deserialize)]
+#[serde(remote = "Self")]
 pub struct ProjectConfig {
     pub name: String,
+    /// secret used to sign cookies, at least 32 bytes
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// names of the server extensions installed into this project's workers
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// middlewares wrapping every route of the project
+    #[serde(default)]
+    pub middlewares: Vec<MiddlewareConfig>,
+    #[serde(default)]
+    pub groups: Vec<RouteGroup>,
+    /// directory of static files served before the routes are matched
+    #[serde(default)]
+    pub public: Option<PathBuf>,
+    /// redirects answered before the routes are matched
+    #[serde(default)]
+    pub redirects: Vec<RedirectConfig>,
+    /// paths routed as another path, invisible to the client
+    #[serde(default)]
+    pub rewrites: Vec<RewriteConfig>,
+    /// cors policy of every route which does not define its own
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// time allowed to read the body and run the handler, e.g. `30s` or `500ms`
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// largest accepted request body, e.g. `1mb`, defaults to 2mb
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_body_size: Option<usize>,
     pub routes: ProjectRoutes,
+    /// the config as canonical yaml, without comments or formatting, to identify a version
+    #[serde(skip)]
+    pub source: String,
+}
+
+impl<'de> Deserialize<'de> for ProjectConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let source = serde_yaml::to_string(&value).map_err(serde::de::Error::custom)?;
+        let mut config = ProjectConfig::deserialize(value).map_err(serde::de::Error::custom)?;
+        config.source = source;
+        Ok(config)
+    }
+}
+
+/// middlewares shared by all routes under a path prefix
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct RouteGroup {
+    pub prefix: String,
+    pub middlewares: Vec<MiddlewareConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum MiddlewareConfig {
+    /// a rust middleware registered on the server
+    Named(String),
+    /// a js function run before the handler, returning a response short-circuits the chain
+    Before { before: String },
+    /// a js function run after the handler, which can replace or modify the response
+    After { after: String },
+}
+
+impl MiddlewareConfig {
+    /// the exported js function of a `before` or `after` middleware
+    pub fn js_name(&self) -> Option<&str> {
+        match self {
+            MiddlewareConfig::Named(_) => None,
+            MiddlewareConfig::Before { before } => Some(before),
+            MiddlewareConfig::After { after } => Some(after),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct CorsConfig {
+    /// allowed origins, `*` matches any origin and `https://*.example.com` any subdomain
+    #[serde(default = "default_cors_origins")]
+    pub origins: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub methods: Vec<String>,
+    /// allowed request headers, the ones requested by the preflight are allowed when unset
+    #[serde(default)]
+    pub headers: Option<Vec<String>>,
+    /// response headers readable by the client
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+    #[serde(default)]
+    pub credentials: bool,
+    /// seconds a preflight result may be cached
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+/// redirect matching paths, `{name}` in `to` is replaced by the matched parameter
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct RedirectConfig {
+    pub from: String,
+    pub to: String,
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+    /// append the query string of the request to the target
+    #[serde(default = "default_true")]
+    pub preserve_query: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct RewriteConfig {
+    pub from: String,
+    pub to: String,
+}
+
+impl CorsConfig {
+    /// `*` with credentials would let any site make credentialed requests
+    fn any_with_credentials(&self) -> bool {
+        self.credentials && self.origins.iter().any(|o| o == "*")
+    }
+}
+
+fn default_redirect_status() -> u16 {
+    301
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_cors_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
 }
 
 impl ProjectConfig {
     pub fn load(filename: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(filename)?;
-        Ok(serde_yaml::from_str(&content)?)
+        let filename = filename.as_ref();
+        let content = std::fs::read_to_string(filename)
+            .with_context(|| format!("failed to read {}", filename.display()))?;
+        let config: Self = serde_yaml::from_str(&content)
+            .with_context(|| format!("invalid config {}", filename.display()))?;
+        config.validate(&[content])?;
+        Ok(config)
+    }
+
+    /// parse and validate a config which doesn't come from a file, e.g. an uploaded one
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let config: Self = serde_yaml::from_str(content).context("invalid config")?;
+        config.validate(&[content.to_string()])?;
+        Ok(config)
+    }
+
+    /// load a config with its profile overlay merged in and environment variables interpolated
+    pub fn load_profile(filename: impl AsRef<Path>, profile: Option<&str>) -> anyhow::Result<Self> {
+        Ok(serde_yaml::from_value(Self::resolve(filename, profile)?)?)
+    }
+
+    /// the config merged with the `config.<profile>.yml` overlay next to it, with `${VAR}` and
+    /// `${VAR:-default}` in values replaced from the environment
+    pub fn resolve(filename: impl AsRef<Path>, profile: Option<&str>) -> anyhow::Result<Value> {
+        let filename = filename.as_ref();
+        let (mut config, content) = read_yaml(filename)?;
+        let mut sources = vec![content];
+        if let Some(profile) = profile {
+            let overlay = profile_path(filename, profile);
+            let (overlay, content) =
+                read_yaml(&overlay).with_context(|| format!("failed to load profile {profile}"))?;
+            merge(&mut config, overlay);
+            sources.push(content);
+        }
+        interpolate(&mut config, &|name| std::env::var(name).ok())?;
+        let parsed: Self = serde_yaml::from_value(config.clone())
+            .with_context(|| format!("invalid config {}", filename.display()))?;
+        parsed.validate(&sources)?;
+        Ok(config)
+    }
+
+    /// reject conflicting route patterns, methods defined twice for a route and cors
+    /// policies allowing credentials from any origin, pointing at the lines of the yaml
+    /// sources the config was loaded from
+    fn validate(&self, sources: &[String]) -> anyhow::Result<()> {
+        if self
+            .cors
+            .as_ref()
+            .is_some_and(CorsConfig::any_with_credentials)
+        {
+            bail!("cors allows credentials from any origin, list the allowed origins instead");
+        }
+        let mut router = matchit::Router::new();
+        for (path, routes) in &self.routes {
+            let at = route_line(sources, path);
+            match router.insert(path.as_str(), ()) {
+                Ok(()) => {}
+                Err(InsertError::Conflict { with }) => {
+                    let with_at = route_line(sources, &with);
+                    bail!("route {path}{at} conflicts with route {with}{with_at}");
+                }
+                Err(e) => bail!("invalid route {path}{at}: {e}"),
+            }
+            for (i, route) in routes.iter().enumerate() {
+                if route
+                    .cors
+                    .as_ref()
+                    .is_some_and(CorsConfig::any_with_credentials)
+                {
+                    bail!(
+                        "cors of route {path}{at} allows credentials from any origin, \
+                         list the allowed origins instead"
+                    );
+                }
+                if routes[..i].iter().any(|r| r.method == route.method) {
+                    bail!(
+                        "method {} is defined twice for route {path}{at}",
+                        route.method
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_yaml(filename: &Path) -> anyhow::Result<(Value, String)> {
+    let content = std::fs::read_to_string(filename)
+        .with_context(|| format!("failed to read {}", filename.display()))?;
+    let value = serde_yaml::from_str(&content)
+        .with_context(|| format!("invalid config {}", filename.display()))?;
+    Ok((value, content))
+}
+
+/// ` (line N)` for the key of a route under `routes:`, empty if it cannot be found
+fn route_line(sources: &[String], path: &str) -> String {
+    let is_key = |line: &str| {
+        let Some(key) = line.trim().strip_suffix(':') else {
+            return false;
+        };
+        let key = key.trim_end();
+        [key, key.trim_matches('"'), key.trim_matches('\'')].contains(&path)
+    };
+    for content in sources {
+        let mut lines = content.lines().enumerate();
+        if lines.by_ref().any(|(_, l)| l.trim_end() == "routes:") {
+            if let Some((n, _)) = lines.find(|(_, l)| is_key(l)) {
+                return format!(" (line {})", n + 1);
+            }
+        }
+    }
+    String::new()
+}
+
+/// `config.yml` becomes `config.<profile>.yml`
+fn profile_path(filename: &Path, profile: &str) -> PathBuf {
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match filename.extension() {
+        Some(ext) => format!("{stem}.{profile}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{profile}"),
+    };
+    filename.with_file_name(name)
+}
+
+/// merge mappings key by key, any other overlay value replaces the base one
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(&k) {
+                    Some(b) => merge(b, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn interpolate(value: &mut Value, env: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+    match value {
+        Value::String(s) => {
+            let ret = substitute(s, env)?;
+            if ret == *s {
+                return Ok(());
+            }
+            // a value which is a single variable keeps the type of what it expands to
+            let whole = s.starts_with("${") && s.find('}') == Some(s.len() - 1);
+            *value = match serde_yaml::from_str(&ret) {
+                Ok(v @ (Value::Number(_) | Value::Bool(_))) if whole => v,
+                _ => Value::String(ret),
+            };
+        }
+        Value::Sequence(seq) => {
+            for v in seq {
+                interpolate(v, env)?;
+            }
+        }
+        Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                interpolate(v, env)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// replace `${VAR}` and `${VAR:-default}`, `$${` is kept as a literal `${`
+fn substitute(s: &str, env: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            ret.push_str(&rest[..start - 1]);
+            ret.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        ret.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            bail!("unterminated variable in {s}");
+        };
+        let var = &rest[start + 2..start + len];
+        let (name, default) = match var.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (var, None),
+        };
+        match env(name).or_else(|| default.map(String::from)) {
+            Some(v) => ret.push_str(&v),
+            None => bail!("environment variable {name} is not set"),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+impl RouteGroup {
+    /// whether the group applies to a route path, matching whole segments only
+    pub fn contains(&self, path: &str) -> bool {
+        let prefix = self.prefix.trim_end_matches('/');
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
     }
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct ProjectRoute {
-    #[serde(deserialize_with = "deserialize_method")]
-    pub method: Method,
+    pub method: RouteMethod,
     pub handler: String,
+    #[serde(default)]
+    pub middlewares: Vec<MiddlewareConfig>,
+    /// overrides the project cors policy for this route
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_body_size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
+}
+
+/// a number of seconds or a string with a `ms`, `s`, `m` or `h` unit
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(NumberOrString::Number(secs)) => return Ok(Some(Duration::from_secs(secs))),
+        Some(NumberOrString::String(s)) => s,
+    };
+    let (n, unit) = split_unit(&s);
+    let n: u64 = n
+        .parse()
+        .map_err(|_| serde::de::Error::custom(format!("invalid duration {s}")))?;
+    let secs = |scale: u64| {
+        n.checked_mul(scale)
+            .map(|secs| Some(Duration::from_secs(secs)))
+            .ok_or_else(|| serde::de::Error::custom(format!("duration {s} is too large")))
+    };
+    match unit {
+        "ms" => Ok(Some(Duration::from_millis(n))),
+        "" | "s" => secs(1),
+        "m" => secs(60),
+        "h" => secs(3600),
+        _ => Err(serde::de::Error::custom(format!("invalid duration {s}"))),
+    }
 }
 
-fn deserialize_method<'de, D>(deserializer: D) -> Result<Method, D::Error>
+/// a number of bytes or a string with a `b`, `kb`, `mb` or `gb` unit
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let s = String::deserialize(deserializer)?;
-    Method::from_bytes(s.as_bytes()).map_err(serde::de::Error::custom)?;
-    match s.to_uppercase().as_str() {
-        "GET" => Ok(Method::GET),
-        "POST" => Ok(Method::POST),
-        "PUT" => Ok(Method::PUT),
-        "DELETE" => Ok(Method::DELETE),
-        "PATCH" => Ok(Method::PATCH),
-        "HEAD" => Ok(Method::HEAD),
-        "OPTIONS" => Ok(Method::OPTIONS),
-        "CONNECT" => Ok(Method::CONNECT),
-        "TRACE" => Ok(Method::TRACE),
-        _ => Err(serde::de::Error::custom("invalid method")),
+    let s = match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(NumberOrString::Number(n)) => return Ok(Some(n as usize)),
+        Some(NumberOrString::String(s)) => s,
+    };
+    let (n, unit) = split_unit(&s);
+    let n: usize = n
+        .parse()
+        .map_err(|_| serde::de::Error::custom(format!("invalid size {s}")))?;
+    let scale = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        _ => return Err(serde::de::Error::custom(format!("invalid size {s}"))),
+    };
+    n.checked_mul(scale)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("size {s} is too large")))
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (&s[..i], s[i..].trim())
+}
+
+/// the method a route answers, `ANY` (or `*`) matches every method without its own route
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RouteMethod {
+    Any,
+    Method(Method),
+}
+
+impl<'de> Deserialize<'de> for RouteMethod {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?.to_uppercase();
+        if s == "ANY" || s == "*" {
+            return Ok(RouteMethod::Any);
+        }
+        Method::from_bytes(s.as_bytes())
+            .map(RouteMethod::Method)
+            .map_err(|_| serde::de::Error::custom(format!("invalid method {s}")))
+    }
+}
+
+impl From<Method> for RouteMethod {
+    fn from(method: Method) -> Self {
+        RouteMethod::Method(method)
+    }
+}
+
+impl fmt::Display for RouteMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteMethod::Any => f.write_str("ANY"),
+            RouteMethod::Method(method) => f.write_str(method.as_str()),
+        }
     }
 }
 
@@ -71,15 +516,251 @@ routes:
             config.routes["/api/hello/id"],
             vec![
                 ProjectRoute {
-                    method: Method::GET,
-                    handler: "hello1".to_string()
+                    method: Method::GET.into(),
+                    handler: "hello1".to_string(),
+                    middlewares: vec![],
+                    cors: None,
+                    timeout: None,
+                    max_body_size: None,
                 },
                 ProjectRoute {
-                    method: Method::POST,
-                    handler: "hello2".to_string()
+                    method: Method::POST.into(),
+                    handler: "hello2".to_string(),
+                    middlewares: vec![],
+                    cors: None,
+                    timeout: None,
+                    max_body_size: None,
                 }
             ]
         );
         Ok(())
     }
+
+    #[test]
+    fn deserialize_middlewares_should_work() -> anyhow::Result<()> {
+        let s = r#"---
+name: dino-test
+middlewares:
+  - request-id
+groups:
+  - prefix: /api/admin/
+    middlewares:
+      - before: auth
+routes:
+  /api/admin/users:
+    - method: GET
+      handler: users
+      middlewares:
+        - after: audit
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(s)?;
+        assert_eq!(
+            config.middlewares,
+            vec![MiddlewareConfig::Named("request-id".to_string())]
+        );
+        assert_eq!(
+            config.groups[0].middlewares,
+            vec![MiddlewareConfig::Before {
+                before: "auth".to_string()
+            }]
+        );
+        assert_eq!(
+            config.routes["/api/admin/users"][0].middlewares,
+            vec![MiddlewareConfig::After {
+                after: "audit".to_string()
+            }]
+        );
+
+        let group = &config.groups[0];
+        assert!(group.contains("/api/admin"));
+        assert!(group.contains("/api/admin/users/{id}"));
+        assert!(!group.contains("/api/administrators"));
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_route_method_should_work() {
+        let method = |s: &str| serde_yaml::from_str::<RouteMethod>(s);
+        assert_eq!(method("get").unwrap(), Method::GET.into());
+        assert_eq!(method("ANY").unwrap(), RouteMethod::Any);
+        assert_eq!(method("'*'").unwrap(), RouteMethod::Any);
+        assert_eq!(
+            method("PROPFIND").unwrap(),
+            Method::from_bytes(b"PROPFIND").unwrap().into()
+        );
+        assert!(method("GE T").is_err());
+    }
+
+    #[test]
+    fn substitute_should_work() -> anyhow::Result<()> {
+        let env = |name: &str| (name == "HOST").then(|| "example.com".to_string());
+        assert_eq!(
+            substitute("https://${HOST}:${PORT:-8080}/", &env)?,
+            "https://example.com:8080/"
+        );
+        assert_eq!(substitute("$${HOST}", &env)?, "${HOST}");
+        assert!(substitute("${MISSING}", &env).is_err());
+        assert!(substitute("${HOST", &env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_profile_should_work() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let base = dir.path().join("config.yml");
+        std::fs::write(
+            &base,
+            r#"
+name: dino-test
+cors:
+  origins: ["*"]
+  max_age: 600
+routes:
+  /api/hello:
+    - method: GET
+      handler: hello
+"#,
+        )?;
+        std::fs::write(
+            dir.path().join("config.prod.yml"),
+            r#"
+secret: ${DINO_TEST_UNSET_SECRET:-0123456789abcdef0123456789abcdef}
+cors:
+  origins: [https://example.com]
+  max_age: ${DINO_TEST_UNSET_MAX_AGE:-3600}
+"#,
+        )?;
+
+        let config = ProjectConfig::load_profile(&base, Some("prod"))?;
+        let cors = config.cors.unwrap();
+        assert_eq!(cors.origins, ["https://example.com"]);
+        assert_eq!(cors.max_age, Some(3600));
+        assert_eq!(config.secret.unwrap().len(), 32);
+        assert_eq!(config.routes["/api/hello"][0].handler, "hello");
+
+        let config = ProjectConfig::load_profile(&base, None)?;
+        assert_eq!(config.cors.unwrap().max_age, Some(600));
+        assert!(ProjectConfig::load_profile(&base, Some("staging")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cors_any_origin_with_credentials_should_be_rejected() {
+        let project = "name: p\ncors: { credentials: true }\nroutes: {}";
+        let err = ProjectConfig::parse(project).unwrap_err();
+        assert!(err.to_string().contains("any origin"), "{err}");
+
+        let route = r#"
+name: p
+routes:
+  /api:
+    - method: GET
+      handler: api
+      cors: { origins: ["*"], credentials: true }
+"#;
+        let err = ProjectConfig::parse(route).unwrap_err();
+        assert!(err.to_string().contains("route /api (line 4)"), "{err}");
+
+        let listed =
+            "name: p\ncors: { origins: [https://example.com], credentials: true }\nroutes: {}";
+        assert!(ProjectConfig::parse(listed).is_ok());
+    }
+
+    #[test]
+    fn routes_should_keep_file_order() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: dino-test
+routes:
+  /c: [{ method: GET, handler: c }]
+  /a: [{ method: GET, handler: a }]
+  /b: [{ method: GET, handler: b }]
+"#,
+        )?;
+        let paths: Vec<_> = config.routes.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["/c", "/a", "/b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_routes_should_report_lines() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("config.yml");
+        std::fs::write(
+            &file,
+            r#"name: dino-test
+routes:
+  /api/users/{id}:
+    - method: GET
+      handler: user
+  "/api/users/{name}":
+    - method: GET
+      handler: byName
+"#,
+        )?;
+        let err = ProjectConfig::load(&file).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "route /api/users/{name} (line 6) conflicts with route /api/users/{id} (line 3)"
+        );
+
+        std::fs::write(
+            &file,
+            r#"name: dino-test
+routes:
+  /api/users/{id}:
+    - method: GET
+      handler: user
+    - method: get
+      handler: user2
+"#,
+        )?;
+        let err = ProjectConfig::load(&file).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "method GET is defined twice for route /api/users/{id} (line 3)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_limits_should_work() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: dino-test
+timeout: 30
+max_body_size: 1mb
+routes:
+  /api/upload:
+    - method: POST
+      handler: upload
+      timeout: 500ms
+      max_body_size: 512KB
+"#,
+        )?;
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.max_body_size, Some(1 << 20));
+        let route = &config.routes["/api/upload"][0];
+        assert_eq!(route.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(route.max_body_size, Some(512 << 10));
+
+        let invalid = "{ name: t, timeout: 5 days, routes: {} }";
+        assert!(serde_yaml::from_str::<ProjectConfig>(invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_limits_should_reject_overflow() {
+        for limit in [
+            "timeout: 99999999999999999h",
+            "max_body_size: 99999999999999TB",
+            "max_body_size: 99999999999999GB",
+        ] {
+            let config = format!("{{ name: t, {limit}, routes: {{}} }}");
+            assert!(ProjectConfig::parse(&config).is_err(), "{limit}");
+        }
+        let err = ProjectConfig::parse("{ name: t, timeout: 99999999999999999h, routes: {} }")
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("too large"), "{err:#}");
+    }
 }
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use axum::http::{header::COOKIE, HeaderMap, HeaderValue};
+use cookie::{time::Duration, Cookie, CookieJar, Key, SameSite};
+use rquickjs::{Ctx, FromJs, IntoJs, Object, Value};
+
+use crate::error::AppError;
+
+const MIN_SECRET_LEN: usize = 32;
+
+/// A cookie set by a handler, serialized into its own `Set-Cookie` header.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ResCookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+    pub signed: bool,
+}
+
+impl<'js> FromJs<'js> for ResCookie {
+    fn from_js(_ctx: &Ctx<'js>, v: Value<'js>) -> rquickjs::Result<Self> {
+        let obj = Object::from_value(v)?;
+        let same_site: Option<String> = obj.get("sameSite")?;
+        let same_site = same_site
+            .map(|s| match s.to_lowercase().as_str() {
+                "strict" => Ok(SameSite::Strict),
+                "lax" => Ok(SameSite::Lax),
+                "none" => Ok(SameSite::None),
+                _ => Err(rquickjs::Error::new_from_js_message(
+                    "string",
+                    "SameSite",
+                    format!("invalid sameSite value {s}"),
+                )),
+            })
+            .transpose()?;
+
+        Ok(Self {
+            name: obj.get("name")?,
+            value: obj.get("value")?,
+            path: obj.get("path")?,
+            domain: obj.get("domain")?,
+            max_age: obj.get("maxAge")?,
+            secure: obj.get::<_, Option<bool>>("secure")?.unwrap_or_default(),
+            http_only: obj.get::<_, Option<bool>>("httpOnly")?.unwrap_or_default(),
+            same_site,
+            signed: obj.get::<_, Option<bool>>("signed")?.unwrap_or_default(),
+        })
+    }
+}
+
+impl<'js> IntoJs<'js> for ResCookie {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+        obj.set("name", self.name)?;
+        obj.set("value", self.value)?;
+        obj.set("path", self.path)?;
+        obj.set("domain", self.domain)?;
+        obj.set("maxAge", self.max_age)?;
+        obj.set("secure", self.secure)?;
+        obj.set("httpOnly", self.http_only)?;
+        let same_site = self.same_site.map(|s| match s {
+            SameSite::Strict => "strict",
+            SameSite::Lax => "lax",
+            SameSite::None => "none",
+        });
+        obj.set("sameSite", same_site)?;
+        obj.set("signed", self.signed)?;
+        Ok(obj.into_value())
+    }
+}
+
+impl ResCookie {
+    /// serialize into a `Set-Cookie` header value, signing it with the project key if requested
+    pub fn to_header(&self, key: Option<&Key>) -> Result<HeaderValue, AppError> {
+        let mut builder = Cookie::build((self.name.clone(), self.value.clone()))
+            .secure(self.secure)
+            .http_only(self.http_only);
+        if let Some(path) = &self.path {
+            builder = builder.path(path.clone());
+        }
+        if let Some(domain) = &self.domain {
+            builder = builder.domain(domain.clone());
+        }
+        if let Some(max_age) = self.max_age {
+            builder = builder.max_age(Duration::seconds(max_age));
+        }
+        if let Some(same_site) = self.same_site {
+            builder = builder.same_site(same_site);
+        }
+
+        let mut cookie = builder.build();
+        if self.signed {
+            let key = key.ok_or_else(|| AppError::CookieSecretMissing(self.name.clone()))?;
+            let mut jar = CookieJar::new();
+            jar.signed_mut(key).add(cookie);
+            cookie = jar.get(&self.name).cloned().expect("cookie was just added");
+        }
+
+        cookie
+            .encoded()
+            .to_string()
+            .parse()
+            .map_err(|_| AppError::InvalidCookie(self.name.clone()))
+    }
+}
+
+/// derive the signing key from the project secret
+pub fn cookie_key(secret: Option<&str>) -> anyhow::Result<Option<Key>> {
+    secret
+        .map(|secret| {
+            if secret.len() < MIN_SECRET_LEN {
+                anyhow::bail!("secret must be at least {MIN_SECRET_LEN} bytes");
+            }
+            Ok(Key::derive_from(secret.as_bytes()))
+        })
+        .transpose()
+}
+
+/// collect the cookies sent in all `cookie` headers
+pub fn parse_cookies(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|s| Cookie::split_parse_encoded(s.to_string()))
+        .filter_map(|c| c.ok())
+        .map(|c| (c.name().to_string(), c.value().to_string()))
+        .collect()
+}
+
+/// keep the cookies whose signature matches the project key, with the signature stripped
+pub fn verify_cookies(cookies: &HashMap<String, String>, key: &Key) -> HashMap<String, String> {
+    let jar = CookieJar::new();
+    let signed = jar.signed(key);
+    cookies
+        .iter()
+        .filter_map(|(k, v)| signed.verify(Cookie::new(k.clone(), v.clone())))
+        .map(|c| (c.name().to_string(), c.value().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "a-very-long-secret-for-signing-dino-cookies";
+
+    #[test]
+    fn parse_cookies_should_work() {
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, "a=1; b=hello%20world".parse().unwrap());
+        headers.append(COOKIE, "c=3".parse().unwrap());
+        let cookies = parse_cookies(&headers);
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["b"], "hello world");
+        assert_eq!(cookies["c"], "3");
+    }
+
+    #[test]
+    fn cookie_to_header_should_work() -> anyhow::Result<()> {
+        let cookie = ResCookie {
+            name: "session".to_string(),
+            value: "abc".to_string(),
+            path: Some("/".to_string()),
+            max_age: Some(3600),
+            http_only: true,
+            same_site: Some(SameSite::Lax),
+            ..Default::default()
+        };
+        let header = cookie.to_header(None)?;
+        let header = header.to_str()?;
+        assert!(header.starts_with("session=abc"));
+        assert!(header.contains("HttpOnly"));
+        assert!(header.contains("SameSite=Lax"));
+        assert!(header.contains("Path=/"));
+        assert!(header.contains("Max-Age=3600"));
+        Ok(())
+    }
+
+    #[test]
+    fn signed_cookie_should_roundtrip() -> anyhow::Result<()> {
+        let key = cookie_key(Some(SECRET))?.unwrap();
+        let cookie = ResCookie {
+            name: "user".to_string(),
+            value: "tyr".to_string(),
+            signed: true,
+            ..Default::default()
+        };
+        let header = cookie.to_header(Some(&key))?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, header);
+        headers.append(COOKIE, "forged=value".parse()?);
+        let cookies = parse_cookies(&headers);
+        let verified = verify_cookies(&cookies, &key);
+        assert_eq!(verified.len(), 1);
+        assert_eq!(verified["user"], "tyr");
+        Ok(())
+    }
+
+    #[test]
+    fn signed_cookie_without_secret_should_fail() {
+        let cookie = ResCookie {
+            name: "user".to_string(),
+            signed: true,
+            ..Default::default()
+        };
+        assert!(cookie.to_header(None).is_err());
+        assert!(cookie_key(Some("short")).is_err());
+    }
+}
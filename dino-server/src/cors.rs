@@ -0,0 +1,228 @@
+use axum::{
+    body::Body,
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+        },
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+
+use crate::{config::CorsConfig, router::AppRouter};
+
+/// answer a cors preflight without running js, `None` if the request is not a preflight
+/// or the requested route has no cors policy
+pub fn preflight(router: &AppRouter, parts: &Parts, path: &str) -> Option<Response> {
+    if parts.method != Method::OPTIONS {
+        return None;
+    }
+    let origin = parts.headers.get(ORIGIN)?.to_str().ok()?;
+    let method = parts.headers.get(ACCESS_CONTROL_REQUEST_METHOD)?;
+    let method = Method::from_bytes(method.as_bytes()).ok()?;
+    let matched = router.match_it(method.clone(), path).ok()?;
+    let cors = matched.value.cors.as_ref()?;
+    let request_headers = parts.headers.get(ACCESS_CONTROL_REQUEST_HEADERS);
+    Some(cors.preflight(origin, &method, request_headers))
+}
+
+/// add the cors headers of a policy to the response of an actual request
+pub fn apply(cors: Option<&CorsConfig>, req: &HeaderMap, res: &mut Response) {
+    if let Some(cors) = cors {
+        let origin = req.get(ORIGIN).and_then(|v| v.to_str().ok());
+        cors.apply(origin, res.headers_mut());
+    }
+}
+
+impl CorsConfig {
+    pub fn preflight(
+        &self,
+        origin: &str,
+        method: &Method,
+        request_headers: Option<&HeaderValue>,
+    ) -> Response {
+        let allowed_method = self
+            .methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method.as_str()));
+        let mut res = Response::new(Body::empty());
+        if !allowed_method || self.allow_origin(origin).is_none() {
+            *res.status_mut() = StatusCode::FORBIDDEN;
+            return res;
+        }
+
+        *res.status_mut() = StatusCode::NO_CONTENT;
+        let headers = res.headers_mut();
+        self.apply(Some(origin), headers);
+        insert(
+            headers,
+            ACCESS_CONTROL_ALLOW_METHODS,
+            &self.methods.join(", "),
+        );
+        match (&self.headers, request_headers) {
+            (Some(allowed), _) => {
+                insert(headers, ACCESS_CONTROL_ALLOW_HEADERS, &allowed.join(", "))
+            }
+            (None, Some(requested)) => {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+                headers.append(
+                    VARY,
+                    HeaderValue::from_static("access-control-request-headers"),
+                );
+            }
+            (None, None) => {}
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.into());
+        }
+        res
+    }
+
+    /// add the cors headers of an actual request, only `vary` for other origins, a cache
+    /// must not serve their response to an allowed one
+    pub fn apply(&self, origin: Option<&str>, headers: &mut HeaderMap) {
+        headers.append(VARY, HeaderValue::from_static("origin"));
+        let Some(allow_origin) = origin.and_then(|origin| self.allow_origin(origin)) else {
+            return;
+        };
+        insert(headers, ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if !self.expose_headers.is_empty() {
+            insert(
+                headers,
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                &self.expose_headers.join(", "),
+            );
+        }
+    }
+
+    /// the `access-control-allow-origin` value for an origin, a bare `*` never reflects
+    /// the origin when credentials are allowed
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        let any = self.origins.iter().any(|o| o == "*");
+        if any && !self.credentials {
+            return Some("*");
+        }
+        let origin_lower = origin.to_ascii_lowercase();
+        self.origins
+            .iter()
+            .filter(|pattern| *pattern != "*")
+            .any(|pattern| matches_origin(&pattern.to_ascii_lowercase(), &origin_lower))
+            .then_some(origin)
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+/// match an origin against a pattern where each `*` stands for any run of characters
+fn matches_origin(pattern: &str, origin: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = origin.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(yaml: &str) -> CorsConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn matches_origin_should_work() {
+        assert!(matches_origin("*", "https://example.com"));
+        assert!(matches_origin("https://example.com", "https://example.com"));
+        assert!(!matches_origin(
+            "https://example.com",
+            "https://example.com.evil"
+        ));
+        assert!(matches_origin(
+            "https://*.example.com",
+            "https://api.example.com"
+        ));
+        assert!(!matches_origin(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!matches_origin("https://*.example.com", "https://evil.com"));
+    }
+
+    #[test]
+    fn preflight_should_work() {
+        let config = cors(
+            r#"
+            origins: ["https://*.example.com"]
+            methods: [GET, POST]
+            credentials: true
+            max_age: 600
+            "#,
+        );
+        let requested = HeaderValue::from_static("content-type");
+        let res = config.preflight("https://app.example.com", &Method::POST, Some(&requested));
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let headers = res.headers();
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_HEADERS], "content-type");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+
+        let res = config.preflight("https://app.example.com", &Method::DELETE, None);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = config.preflight("https://evil.com", &Method::GET, None);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn apply_should_work() {
+        let config = cors("expose_headers: [x-total]");
+        let mut headers = HeaderMap::new();
+        config.apply(Some("https://example.com"), &mut headers);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[ACCESS_CONTROL_EXPOSE_HEADERS], "x-total");
+        assert_eq!(headers[VARY], "origin");
+
+        let config = cors("origins: [https://example.com]");
+        for origin in [Some("https://evil.com"), None] {
+            let mut headers = HeaderMap::new();
+            config.apply(origin, &mut headers);
+            assert_eq!(headers.len(), 1);
+            assert_eq!(headers[VARY], "origin");
+        }
+
+        let config = cors("{ origins: ['*'], credentials: true }");
+        let mut headers = HeaderMap::new();
+        config.apply(Some("https://evil.com"), &mut headers);
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
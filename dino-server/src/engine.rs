@@ -1,17 +1,44 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use axum::{body::Body, response::Response};
-use dino_macros::{FromJs, IntoJs};
-use rquickjs::{Context, Function, Object, Promise, Runtime};
+use anyhow::Context as _;
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CONTENT_TYPE, SET_COOKIE},
+        StatusCode,
+    },
+    response::Response,
+};
+use cookie::Key;
+use dino_macros::{IntoJs, TypeScript};
+use rquickjs::{
+    async_with, function::Constructor, promise::MaybePromise, AsyncContext, AsyncRuntime, Coerced,
+    Context, Ctx, FromJs, Function, IntoJs, Object, Runtime, Type, Value,
+};
 use typed_builder::TypedBuilder;
 
+use crate::{cookies::ResCookie, error::AppError, extension::Extension};
+
+const RESPONSE_JS: &str = include_str!("js/response.js");
+const GLOBALS_DTS: &str = include_str!("js/globals.d.ts");
+const TEXT_PLAIN: &str = "text/plain; charset=utf-8";
+const APPLICATION_JSON: &str = "application/json";
+/// time a bundle may spend evaluating its top level when no earlier deadline applies
+pub const EVAL_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[allow(unused)]
 pub struct JsWorker {
-    rt: Runtime,
-    ctx: Context,
+    rt: AsyncRuntime,
+    ctx: AsyncContext,
 }
 
-#[derive(Debug, TypedBuilder, IntoJs)]
+#[derive(Debug, Clone, TypedBuilder, IntoJs, dino_macros::FromJs, TypeScript)]
+#[js(rename_all = "camelCase")]
 pub struct Req {
     #[builder(setter(into))]
     pub method: String,
@@ -24,13 +51,21 @@ pub struct Req {
     #[builder(default)]
     pub headers: HashMap<String, String>,
     #[builder(default)]
+    pub cookies: HashMap<String, String>,
+    #[builder(default)]
+    pub signed_cookies: HashMap<String, String>,
+    #[builder(default)]
     pub body: Option<String>,
+    /// the path prefix the project is mounted at, empty when it owns the whole host
+    #[builder(default, setter(into))]
+    pub base_path: String,
 }
 
-#[derive(Debug, FromJs)]
+#[derive(Debug, Clone)]
 pub struct Res {
     pub status: u16,
     pub headers: HashMap<String, String>,
+    pub cookies: Vec<ResCookie>,
     pub body: Option<String>,
 }
 
@@ -60,47 +95,268 @@ impl<'js> FromJs<'js> for Res {
 }
 */
 
+impl<'js> FromJs<'js> for Res {
+    fn from_js(ctx: &Ctx<'js>, v: Value<'js>) -> rquickjs::Result<Self> {
+        match v.type_of() {
+            Type::Undefined | Type::Null => Ok(Res::new(StatusCode::NO_CONTENT.as_u16())),
+            Type::String => Ok(Res::new(200).with_body(v.get()?, TEXT_PLAIN)),
+            Type::Object => {
+                let obj = v.as_object().expect("value type is object");
+                if is_response(ctx, obj)? {
+                    Res::from_parts(ctx, obj)
+                } else {
+                    Ok(Res::new(200).with_body(stringify(ctx, v)?, APPLICATION_JSON))
+                }
+            }
+            _ => Ok(Res::new(200).with_body(stringify(ctx, v)?, APPLICATION_JSON)),
+        }
+    }
+}
+
+/// a `Response` instance, so `after` middlewares get the same helpers as handlers
+impl<'js> IntoJs<'js> for Res {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let class: Constructor = ctx.globals().get("Response")?;
+        let init = Object::new(ctx.clone())?;
+        init.set("status", self.status)?;
+        init.set("headers", self.headers)?;
+        init.set("cookies", self.cookies)?;
+        class.construct((self.body, init))
+    }
+}
+
+impl Res {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: HashMap::new(),
+            cookies: Vec::new(),
+            body: None,
+        }
+    }
+
+    /// set the body and fall back to the given content type if none is set
+    fn with_body(mut self, body: String, content_type: &str) -> Self {
+        let has_content_type = self
+            .headers
+            .keys()
+            .any(|k| k.eq_ignore_ascii_case(CONTENT_TYPE.as_str()));
+        if !has_content_type {
+            self.headers
+                .insert(CONTENT_TYPE.to_string(), content_type.to_string());
+        }
+        self.body = Some(body);
+        self
+    }
+
+    /// build from a `Response` instance or a partial `{ status, headers, cookies, body }` object
+    fn from_parts<'js>(ctx: &Ctx<'js>, obj: &Object<'js>) -> rquickjs::Result<Self> {
+        let status: Option<u16> = obj.get("status")?;
+        let status = status.unwrap_or(200);
+        if StatusCode::from_u16(status).is_err() {
+            return Err(rquickjs::Error::new_from_js_message(
+                "object",
+                "Res",
+                format!("invalid status code {status}"),
+            ));
+        }
+
+        let headers: Option<HashMap<String, Coerced<String>>> = obj.get("headers")?;
+        let mut res = Res::new(status);
+        res.headers = headers
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, v.0))
+            .collect();
+        let cookies: Option<Vec<ResCookie>> = obj.get("cookies")?;
+        res.cookies = cookies.unwrap_or_default();
+
+        let body: Value = obj.get("body")?;
+        let res = match body.type_of() {
+            Type::Undefined | Type::Null => res,
+            Type::String => res.with_body(body.get()?, TEXT_PLAIN),
+            _ => res.with_body(stringify(ctx, body)?, APPLICATION_JSON),
+        };
+        Ok(res)
+    }
+}
+
+/// whether an object returned by a handler describes the response rather than being the payload
+fn is_response<'js>(ctx: &Ctx<'js>, obj: &Object<'js>) -> rquickjs::Result<bool> {
+    let class: Value = ctx.globals().get("Response")?;
+    if class.is_constructor() && obj.is_instance_of(&class) {
+        return Ok(true);
+    }
+    if obj.is_array() {
+        return Ok(false);
+    }
+
+    let mut described = false;
+    for key in obj.keys::<String>() {
+        match key?.as_str() {
+            "status" => {
+                let status: Value = obj.get("status")?;
+                if !status.is_number() {
+                    return Ok(false);
+                }
+                described = true;
+            }
+            "body" => described = true,
+            "headers" | "cookies" => {}
+            _ => return Ok(false),
+        }
+    }
+    Ok(described)
+}
+
+fn stringify<'js>(ctx: &Ctx<'js>, v: Value<'js>) -> rquickjs::Result<String> {
+    match ctx.json_stringify(v)? {
+        Some(s) => s.to_string(),
+        None => Ok("null".to_string()),
+    }
+}
+
+/// TypeScript declarations of the handler api, written into projects as `dino.d.ts`
+pub fn ts_declarations() -> String {
+    format!("{}\n{}", Req::TS_DECLARATION, GLOBALS_DTS)
+}
+
 fn print(msg: String) {
     println!("{msg}");
 }
 
+/// the names exported by a bundle and whether each one is a function, evaluated with the
+/// extensions of its workers
+pub fn exports(
+    module: &str,
+    extensions: &[Arc<dyn Extension>],
+) -> anyhow::Result<HashMap<String, bool>> {
+    let rt = Runtime::new()?;
+    rt.set_interrupt_handler(Some(interrupt_at(Instant::now() + EVAL_TIMEOUT)));
+    let ctx = Context::full(&rt)?;
+    ctx.with(|ctx| {
+        ctx.eval::<(), _>(RESPONSE_JS)?;
+        let fun = Function::new(ctx.clone(), print)?.with_name("print")?;
+        ctx.globals().set("print", fun)?;
+        for ext in extensions {
+            ext.register(&ctx)
+                .with_context(|| format!("failed to register extension {}", ext.name()))?;
+        }
+        let ret: Object = ctx.eval(module)?;
+        ret.props::<String, Value>()
+            .map(|prop| {
+                let (name, value) = prop?;
+                Ok((name, value.is_function()))
+            })
+            .collect()
+    })
+}
+
+fn interrupt_at(deadline: Instant) -> Box<dyn FnMut() -> bool + Send> {
+    Box::new(move || Instant::now() >= deadline)
+}
+
 impl JsWorker {
-    pub fn try_new(module: &str) -> anyhow::Result<Self> {
-        let rt = Runtime::new()?;
-        let ctx = Context::full(&rt)?;
+    /// a worker whose js is interrupted at the deadline, evaluating the bundle included,
+    /// which is limited to `EVAL_TIMEOUT` without a deadline
+    pub async fn try_new(
+        module: &str,
+        extensions: &[Arc<dyn Extension>],
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<Self> {
+        let rt = AsyncRuntime::new()?;
+        let eval_deadline = deadline.unwrap_or_else(|| Instant::now() + EVAL_TIMEOUT);
+        rt.set_interrupt_handler(Some(interrupt_at(eval_deadline)))
+            .await;
+        let ctx = AsyncContext::full(&rt).await?;
+        let module = module.to_string();
+        let extensions = extensions.to_vec();
 
-        ctx.with(|ctx| {
+        async_with!(ctx => |ctx| {
             let global = ctx.globals();
-            let ret: Object = ctx.eval(module)?;
-            global.set("handlers", ret)?;
+            ctx.eval::<(), _>(RESPONSE_JS)?;
             let fun = Function::new(ctx.clone(), print)?.with_name("print")?;
             global.set("print", fun)?;
+            for ext in extensions {
+                ext.register(&ctx)
+                    .with_context(|| format!("failed to register extension {}", ext.name()))?;
+            }
+            let ret: Object = ctx.eval(module)?;
+            global.set("handlers", ret)?;
             Ok::<_, anyhow::Error>(())
-        })?;
+        })
+        .await?;
+        if deadline.is_none() {
+            rt.set_interrupt_handler(None).await;
+        }
         Ok(Self { rt, ctx })
     }
 
-    pub fn run(&self, name: &str, req: Req) -> anyhow::Result<Res> {
-        self.ctx.with(|ctx| {
-            let global = ctx.globals();
-            let handlers: Object = global.get("handlers")?;
-            let fun: Function = handlers.get(name)?;
-            let v: Promise = fun.call((req,))?;
-            Ok::<_, anyhow::Error>(v.finish()?)
+    /// run a `before` middleware, returning the possibly modified request and, if the
+    /// middleware returned a value, the response short-circuiting the chain
+    pub async fn run_before(&self, name: &str, req: Req) -> anyhow::Result<(Req, Option<Res>)> {
+        let name = name.to_string();
+        async_with!(self.ctx => |ctx| {
+            let fun = get_handler(&ctx, &name)?;
+            let req = req.into_js(&ctx)?;
+            let v: MaybePromise = fun.call((req.clone(),))?;
+            let ret: Value = v.into_future().await?;
+            let res = if ret.is_undefined() {
+                None
+            } else {
+                Some(Res::from_js(&ctx, ret)?)
+            };
+            Ok::<_, anyhow::Error>((Req::from_js(&ctx, req)?, res))
+        })
+        .await
+    }
+
+    /// run an `after` middleware, which either returns a new response or modifies the given one
+    pub async fn run_after(&self, name: &str, req: Req, res: Res) -> anyhow::Result<Res> {
+        let name = name.to_string();
+        async_with!(self.ctx => |ctx| {
+            let fun = get_handler(&ctx, &name)?;
+            let res = res.into_js(&ctx)?;
+            let v: MaybePromise = fun.call((req, res.clone()))?;
+            let ret: Value = v.into_future().await?;
+            let ret = if ret.is_undefined() { res } else { ret };
+            Ok::<_, anyhow::Error>(Res::from_js(&ctx, ret)?)
         })
+        .await
     }
+
+    pub async fn run(&self, name: &str, req: Req) -> anyhow::Result<Res> {
+        let name = name.to_string();
+        async_with!(self.ctx => |ctx| {
+            let fun = get_handler(&ctx, &name)?;
+            let v: MaybePromise = fun.call((req,))?;
+            Ok::<_, anyhow::Error>(v.into_future::<Res>().await?)
+        })
+        .await
+    }
+}
+
+fn get_handler<'js>(ctx: &Ctx<'js>, name: &str) -> anyhow::Result<Function<'js>> {
+    let handlers: Object = ctx.globals().get("handlers")?;
+    let fun: Option<Function> = handlers.get(name)?;
+    fun.with_context(|| format!("function {name} is not exported"))
 }
 
-impl From<Res> for Response {
-    fn from(value: Res) -> Self {
-        let mut builder = Response::builder().status(value.status);
-        for (k, v) in value.headers {
+impl Res {
+    /// convert into an http response, signing cookies with the project key when requested
+    pub fn into_response(self, key: Option<&Key>) -> Result<Response, AppError> {
+        let mut builder = Response::builder().status(self.status);
+        for (k, v) in self.headers {
             builder = builder.header(k, v);
         }
-        match value.body {
-            Some(body) => builder.body(body.into()).unwrap(),
-            None => builder.body(Body::empty()).unwrap(),
+        for cookie in &self.cookies {
+            builder = builder.header(SET_COOKIE, cookie.to_header(key)?);
         }
+        let body = match self.body {
+            Some(body) => body.into(),
+            None => Body::empty(),
+        };
+        Ok(builder.body(body).map_err(anyhow::Error::from)?)
     }
 }
 
@@ -108,8 +364,8 @@ impl From<Res> for Response {
 mod tests {
     use super::*;
 
-    #[test]
-    fn js_worker_run_func_should_work() {
+    #[tokio::test]
+    async fn js_worker_run_func_should_work() {
         let code = r#"
         (function(){
             async function hello(req){
@@ -129,8 +385,190 @@ mod tests {
             .url("https://example.com")
             .headers(HashMap::new())
             .build();
-        let worker = JsWorker::try_new(code).unwrap();
-        let ret = worker.run("hello", req).unwrap();
+        let worker = JsWorker::try_new(code, &[], None).await.unwrap();
+        let ret = worker.run("hello", req).await.unwrap();
         assert_eq!(ret.status, 200);
     }
+
+    async fn run_handler(body: &str) -> Res {
+        let code = format!(
+            "(function(){{ async function hello(req){{ {body} }} return {{hello:hello}}; }})();"
+        );
+        let req = Req::builder().method("GET").url("/").build();
+        let worker = JsWorker::try_new(&code, &[], None).await.unwrap();
+        worker.run("hello", req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn string_return_should_be_text() {
+        let ret = run_handler(r#"return "hello";"#).await;
+        assert_eq!(ret.status, 200);
+        assert_eq!(ret.headers["content-type"], TEXT_PLAIN);
+        assert_eq!(ret.body.as_deref(), Some("hello"));
+    }
+
+    #[tokio::test]
+    async fn plain_object_and_array_return_should_be_json() {
+        let ret = run_handler(r#"return { name: "dino", status: "ok" };"#).await;
+        assert_eq!(ret.status, 200);
+        assert_eq!(ret.headers["content-type"], APPLICATION_JSON);
+        assert_eq!(
+            ret.body.as_deref(),
+            Some(r#"{"name":"dino","status":"ok"}"#)
+        );
+
+        let ret = run_handler("return [1, 2];").await;
+        assert_eq!(ret.body.as_deref(), Some("[1,2]"));
+    }
+
+    #[tokio::test]
+    async fn null_return_should_be_no_content() {
+        let ret = run_handler("return null;").await;
+        assert_eq!(ret.status, 204);
+        assert!(ret.body.is_none());
+
+        let ret = run_handler("").await;
+        assert_eq!(ret.status, 204);
+    }
+
+    #[tokio::test]
+    async fn partial_object_return_should_use_defaults() {
+        let ret = run_handler(r#"return { status: 201, body: { id: 1 } };"#).await;
+        assert_eq!(ret.status, 201);
+        assert_eq!(ret.headers["content-type"], APPLICATION_JSON);
+        assert_eq!(ret.body.as_deref(), Some(r#"{"id":1}"#));
+
+        let ret =
+            run_handler(r#"return { body: "hi", headers: { "Content-Type": "text/html" } };"#)
+                .await;
+        assert_eq!(ret.status, 200);
+        assert_eq!(ret.headers.len(), 1);
+        assert_eq!(ret.headers["Content-Type"], "text/html");
+    }
+
+    #[tokio::test]
+    async fn response_instance_return_should_work() {
+        let ret = run_handler(
+            r#"return new Response("created", { status: 201, headers: { "x-id": 1 } });"#,
+        )
+        .await;
+        assert_eq!(ret.status, 201);
+        assert_eq!(ret.headers["x-id"], "1");
+        assert_eq!(ret.body.as_deref(), Some("created"));
+
+        let ret = run_handler(r#"return Response.json({ ok: true });"#).await;
+        assert_eq!(ret.headers["content-type"], APPLICATION_JSON);
+        assert_eq!(ret.body.as_deref(), Some(r#"{"ok":true}"#));
+    }
+
+    #[tokio::test]
+    async fn invalid_status_should_fail() {
+        let code = "(function(){ function hello(req){ return { status: 1000 }; } return {hello:hello}; })();";
+        let req = Req::builder().method("GET").url("/").build();
+        let worker = JsWorker::try_new(code, &[], None).await.unwrap();
+        assert!(worker.run("hello", req).await.is_err());
+    }
+
+    struct Greeter;
+
+    impl Extension for Greeter {
+        fn name(&self) -> &str {
+            "greeter"
+        }
+
+        fn register<'js>(&self, ctx: &Ctx<'js>) -> rquickjs::Result<()> {
+            let greet = Function::new(ctx.clone(), |name: String| format!("hello {name}"))?;
+            ctx.globals().set("greet", greet)
+        }
+    }
+
+    #[tokio::test]
+    async fn extensions_should_be_registered_before_handlers() {
+        let code = r#"
+        (function(){
+            const prefix = greet("dino");
+            function hello(req){ return `${prefix} from ${req.url}`; }
+            return {hello:hello};
+        })();
+        "#;
+        let req = Req::builder().method("GET").url("/").build();
+        let extensions: Vec<Arc<dyn Extension>> = vec![Arc::new(Greeter)];
+        let worker = JsWorker::try_new(code, &extensions, None).await.unwrap();
+        let ret = worker.run("hello", req).await.unwrap();
+        assert_eq!(ret.body.as_deref(), Some("hello dino from /"));
+
+        assert!(JsWorker::try_new(code, &[], None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deadline_should_interrupt_js() {
+        let code = "(function(){ function spin(){ while(true){} } return {spin}; })();";
+        let req = Req::builder().method("GET").url("/").build();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let worker = JsWorker::try_new(code, &[], Some(deadline)).await.unwrap();
+        assert!(worker.run("spin", req).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deadline_should_interrupt_bundle_evaluation() {
+        let code = "(function(){ while(true){} return {}; })();";
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let worker = JsWorker::try_new(code, &[], Some(deadline)).await;
+        assert!(worker.is_err());
+    }
+
+    #[test]
+    fn exports_should_interrupt_spinning_bundles() {
+        let started = Instant::now();
+        assert!(exports("(function(){ while(true){} return {}; })();", &[]).is_err());
+        assert!(started.elapsed() < EVAL_TIMEOUT * 2);
+    }
+
+    #[test]
+    fn exports_should_work() -> anyhow::Result<()> {
+        let code = "(function(){ function hello(){} return { hello, version: 1 }; })();";
+        let ret = exports(code, &[])?;
+        assert_eq!(ret.len(), 2);
+        assert!(ret["hello"]);
+        assert!(!ret["version"]);
+        let greeting = "(function(){ greet('dino'); return {}; })();";
+        assert!(exports(greeting, &[]).is_err());
+        assert!(exports(greeting, &[Arc::new(Greeter)]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn ts_declarations_should_work() {
+        let dts = ts_declarations();
+        assert!(dts.starts_with("interface Req {\n  method: string;"));
+        assert!(dts.contains("  signedCookies: Record<string, string>;\n"));
+        assert!(dts.contains("  basePath: string;\n"));
+        assert!(dts.contains("  body?: string | null;\n"));
+        assert!(dts.contains("declare class Response"));
+    }
+
+    #[tokio::test]
+    async fn response_cookies_should_work() -> anyhow::Result<()> {
+        let ret = run_handler(
+            r#"
+            const res = new Response("ok");
+            res.setCookie("a", "1", { httpOnly: true, sameSite: "strict" });
+            res.setCookie("b", "2", { maxAge: 60, path: "/" });
+            return res;
+            "#,
+        )
+        .await;
+        assert_eq!(ret.cookies.len(), 2);
+        assert!(ret.cookies[0].http_only);
+        assert_eq!(ret.cookies[1].max_age, Some(60));
+
+        let res = ret.into_response(None)?;
+        let cookies: Vec<_> = res.headers().get_all(SET_COOKIE).iter().collect();
+        assert_eq!(cookies.len(), 2);
+
+        let ret =
+            run_handler(r#"return { body: "ok", cookies: [{ name: "c", value: "3" }] };"#).await;
+        assert_eq!(ret.cookies[0].name, "c");
+        Ok(())
+    }
 }
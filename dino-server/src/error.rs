@@ -1,5 +1,7 @@
+use std::time::Duration;
+
 use axum::{
-    http::{Method, StatusCode},
+    http::{header::ALLOW, HeaderValue, Method, StatusCode},
     response::IntoResponse,
 };
 use thiserror::Error;
@@ -13,7 +15,40 @@ pub enum AppError {
     RoutePathNotFound(String),
 
     #[error("Method not found: {0}")]
-    RouteMethodNotAllowed(Method),
+    RouteMethodNotAllowed(Method, String),
+
+    #[error("Request timed out after {0:?}")]
+    RequestTimeout(Duration),
+
+    #[error("Request body is larger than {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("Cookie {0} must be signed but the project has no secret")]
+    CookieSecretMissing(String),
+
+    #[error("Invalid cookie: {0}")]
+    InvalidCookie(String),
+
+    #[error("Extension not registered on the server: {0}")]
+    ExtensionNotFound(String),
+
+    #[error("Middleware not registered on the server: {0}")]
+    MiddlewareNotFound(String),
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Tenant not found: {0}")]
+    TenantNotFound(String),
+
+    #[error("Version not found: {0}")]
+    VersionNotFound(String),
+
+    #[error("Canary not found: {0}")]
+    CanaryNotFound(String),
+
+    #[error("Invalid tenant: {0:#}")]
+    InvalidTenant(anyhow::Error),
 
     #[error("Anyhow error: {0}")]
     Anyhow(#[from] anyhow::Error),
@@ -27,10 +62,29 @@ impl IntoResponse for AppError {
         let code = match self {
             AppError::HostNotFound(_) => StatusCode::NOT_FOUND,
             AppError::RoutePathNotFound(_) => StatusCode::NOT_FOUND,
-            AppError::RouteMethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::RouteMethodNotAllowed(..) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::RequestTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::CookieSecretMissing(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::InvalidCookie(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ExtensionNotFound(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::MiddlewareNotFound(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::TenantNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::VersionNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::CanaryNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidTenant(_) => StatusCode::BAD_REQUEST,
             AppError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Serde(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
-        (code, self.to_string()).into_response()
+        let allow = match &self {
+            AppError::RouteMethodNotAllowed(_, allow) => HeaderValue::from_str(allow).ok(),
+            _ => None,
+        };
+        let mut res = (code, self.to_string()).into_response();
+        if let Some(allow) = allow {
+            res.headers_mut().insert(ALLOW, allow);
+        }
+        res
     }
 }
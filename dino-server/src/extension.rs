@@ -0,0 +1,73 @@
+use std::{collections::HashMap, sync::Arc};
+
+use rquickjs::Ctx;
+
+use crate::error::AppError;
+
+/// A set of host bindings an embedder installs into the js context of every new worker.
+/// Tenants opt in by listing the extension name under `extensions` in their project config.
+pub trait Extension: Send + Sync + 'static {
+    /// name used to enable the extension in the project config
+    fn name(&self) -> &str;
+
+    /// register globals or modules on a freshly created context, before the handlers are evaluated
+    fn register<'js>(&self, ctx: &Ctx<'js>) -> rquickjs::Result<()>;
+}
+
+#[derive(Clone, Default)]
+pub struct Extensions(Arc<HashMap<String, Arc<dyn Extension>>>);
+
+impl Extensions {
+    pub fn new(extensions: impl IntoIterator<Item = Arc<dyn Extension>>) -> Self {
+        let map = extensions
+            .into_iter()
+            .map(|ext| (ext.name().to_string(), ext))
+            .collect();
+        Self(Arc::new(map))
+    }
+
+    /// look up the extensions enabled by a project, in the order they are listed
+    pub fn resolve(&self, names: &[String]) -> Result<Vec<Arc<dyn Extension>>, AppError> {
+        names
+            .iter()
+            .map(|name| {
+                self.0
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| AppError::ExtensionNotFound(name.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop(&'static str);
+
+    impl Extension for Noop {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn register<'js>(&self, _ctx: &Ctx<'js>) -> rquickjs::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn resolve_should_keep_config_order() {
+        let extensions = Extensions::new([
+            Arc::new(Noop("auth")) as Arc<dyn Extension>,
+            Arc::new(Noop("kv")),
+        ]);
+        let names = ["kv".to_string(), "auth".to_string()];
+        let resolved = extensions.resolve(&names).unwrap();
+        let resolved: Vec<_> = resolved.iter().map(|ext| ext.name()).collect();
+        assert_eq!(resolved, ["kv", "auth"]);
+
+        let ret = extensions.resolve(&["mail".to_string()]);
+        assert!(matches!(ret, Err(AppError::ExtensionNotFound(name)) if name == "mail"));
+    }
+}
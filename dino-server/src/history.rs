@@ -0,0 +1,133 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context as _};
+use serde::{Deserialize, Serialize};
+
+use crate::router::HISTORY_LIMIT;
+
+const VERSIONS_FILE: &str = "versions.json";
+
+/// The versions deployed through the admin api, kept on disk to roll back to after a restart.
+///
+/// Every tenant gets a directory with `<hash>.mjs` and `<hash>.yml` per version and
+/// a `versions.json` listing them newest first.
+#[derive(Debug, Clone)]
+pub struct DeployHistory {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Version {
+    pub hash: String,
+    /// unix timestamp in seconds
+    pub deployed_at: u64,
+}
+
+impl DeployHistory {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// store a deployed version, dropping the oldest ones beyond the history limit
+    pub fn record(&self, tenant: &str, hash: &str, code: &str, config: &str) -> anyhow::Result<()> {
+        let dir = self.tenant_dir(tenant)?;
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(format!("{hash}.mjs")), code)?;
+        fs::write(dir.join(format!("{hash}.yml")), config)?;
+
+        let mut versions = self.versions(tenant)?;
+        versions.retain(|v| v.hash != hash);
+        versions.insert(
+            0,
+            Version {
+                hash: hash.to_string(),
+                deployed_at: unix_secs(SystemTime::now()),
+            },
+        );
+        for dropped in versions.drain(HISTORY_LIMIT.min(versions.len())..) {
+            for ext in ["mjs", "yml"] {
+                let _ = fs::remove_file(dir.join(format!("{}.{ext}", dropped.hash)));
+            }
+        }
+        fs::write(
+            dir.join(VERSIONS_FILE),
+            serde_json::to_vec_pretty(&versions)?,
+        )?;
+        Ok(())
+    }
+
+    /// the stored versions of a tenant, newest first
+    pub fn versions(&self, tenant: &str) -> anyhow::Result<Vec<Version>> {
+        let path = self.tenant_dir(tenant)?.join(VERSIONS_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read(&path)?;
+        serde_json::from_slice(&content).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// the code and config of a stored version
+    pub fn load(&self, tenant: &str, hash: &str) -> anyhow::Result<(String, String)> {
+        if !self.versions(tenant)?.iter().any(|v| v.hash == hash) {
+            bail!("version {hash} of {tenant} not found");
+        }
+        let dir = self.tenant_dir(tenant)?;
+        let code = fs::read_to_string(dir.join(format!("{hash}.mjs")))?;
+        let config = fs::read_to_string(dir.join(format!("{hash}.yml")))?;
+        Ok((code, config))
+    }
+
+    fn tenant_dir(&self, tenant: &str) -> anyhow::Result<PathBuf> {
+        let valid = !tenant.is_empty()
+            && !tenant.starts_with('.')
+            && Path::new(tenant).components().count() == 1
+            && !tenant.contains(['/', '\\']);
+        if !valid {
+            bail!("invalid tenant name {tenant}");
+        }
+        Ok(self.dir.join(tenant))
+    }
+}
+
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_should_be_bounded() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let history = DeployHistory::new(dir.path());
+        for i in 0..HISTORY_LIMIT + 2 {
+            history.record("billing", &format!("v{i}"), "code", "config")?;
+        }
+        let versions = history.versions("billing")?;
+        assert_eq!(versions.len(), HISTORY_LIMIT);
+        assert_eq!(versions[0].hash, format!("v{}", HISTORY_LIMIT + 1));
+        assert!(history.load("billing", "v0").is_err());
+        assert!(!dir.path().join("billing/v0.mjs").exists());
+
+        // a redeployed version moves to the front
+        history.record("billing", "v5", "code5", "config5")?;
+        let versions = history.versions("billing")?;
+        assert_eq!(versions[0].hash, "v5");
+        assert_eq!(versions.len(), HISTORY_LIMIT);
+        assert_eq!(
+            history.load("billing", "v5")?,
+            ("code5".to_string(), "config5".to_string())
+        );
+
+        assert!(history.record("../etc", "v1", "code", "config").is_err());
+        assert!(history.versions("search")?.is_empty());
+        Ok(())
+    }
+}
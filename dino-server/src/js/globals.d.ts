@@ -0,0 +1,46 @@
+interface Cookie {
+  name: string;
+  value: string;
+  path?: string;
+  domain?: string;
+  maxAge?: number;
+  secure?: boolean;
+  httpOnly?: boolean;
+  sameSite?: 'strict' | 'lax' | 'none';
+  signed?: boolean;
+}
+
+type CookieOptions = Omit<Cookie, 'name' | 'value'>;
+
+interface ResponseInit {
+  status?: number;
+  headers?: Record<string, string | number>;
+  cookies?: Cookie[];
+}
+
+declare class Response {
+  constructor(body?: unknown, init?: ResponseInit);
+  status: number;
+  headers: Record<string, string>;
+  cookies: Cookie[];
+  body: unknown;
+  setCookie(name: string, value: string, options?: CookieOptions): this;
+  clearCookie(name: string, options?: CookieOptions): this;
+  static json(data: unknown, init?: ResponseInit): Response;
+}
+
+// strings are sent as text, other values as json and null as 204
+type Res = Response | (ResponseInit & { body?: unknown }) | string | number | boolean | object | null | void;
+
+type Handler = (req: Req) => Res | Promise<Res>;
+
+// `before` middlewares may modify `req`, returning anything but undefined short-circuits the handler
+type Before = (req: Req) => Res | undefined | Promise<Res | undefined>;
+
+// `after` middlewares may modify `res` in place or return a replacement
+type After = (req: Req, res: Response) => Res | undefined | Promise<Res | undefined>;
+
+declare function print(msg: string): void;
+
+// fingerprinted url of each file in the public directory, defined when the project has one
+declare const assets: Record<string, string>;
@@ -0,0 +1,24 @@
+class Response {
+  constructor(body = null, init = {}) {
+    this.status = init.status ?? 200;
+    this.headers = { ...(init.headers ?? {}) };
+    this.cookies = [...(init.cookies ?? [])];
+    this.body = body;
+  }
+
+  setCookie(name, value, options = {}) {
+    this.cookies.push({ ...options, name, value });
+    return this;
+  }
+
+  clearCookie(name, options = {}) {
+    return this.setCookie(name, '', { ...options, maxAge: 0 });
+  }
+
+  static json(data, init = {}) {
+    const headers = { 'content-type': 'application/json', ...(init.headers ?? {}) };
+    return new Response(JSON.stringify(data), { ...init, headers });
+  }
+}
+
+globalThis.Response = Response;
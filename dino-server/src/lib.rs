@@ -1,71 +1,251 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    future::IntoFuture,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Instant,
+};
 
+pub use admin::TenantInfo;
+use anyhow::bail;
+pub use assets::ASSETS_MANIFEST;
 use axum::{
-    body::Bytes,
+    body::{Body, Bytes},
     extract::{Host, Query, State},
-    http::{request::Parts, Response},
-    response::IntoResponse,
+    http::{
+        header::{CONTENT_LENGTH, SET_COOKIE},
+        request::Parts,
+        HeaderMap, HeaderValue, Method,
+    },
+    response::{IntoResponse, Response},
     routing::any,
     Router,
 };
+pub use canary::{Canary, StatsSnapshot, VersionStats};
 pub use config::ProjectConfig;
-use dashmap::DashMap;
-use engine::{JsWorker, Req};
+use cookie::Key;
+pub use cookies::ResCookie;
+use cookies::{parse_cookies, verify_cookies};
+pub use engine::ts_declarations;
+use engine::JsWorker;
+pub use engine::{Req, Res};
 use error::AppError;
+pub use extension::{Extension, Extensions};
+pub use history::{DeployHistory, Version};
+use http_body_util::LengthLimitError;
 use matchit::Match;
-use middleware::ServerTimeLayer;
-pub use router::{AppRouter, SwappableAppRouter};
-use tokio::net::TcpListener;
+pub use middleware::Middleware;
+use middleware::{run_chain, Middlewares, ServerTimeLayer};
+use router::RouteHandler;
+pub use router::{validate_handlers, AppRouter, SwappableAppRouter};
+pub use rquickjs;
+pub use tenant::{SwappableTenants, Tenants, TenentRouter};
+pub use tls::TlsListener;
+use tokio::{net::TcpListener, task::JoinSet};
 use tracing::info;
 
+mod admin;
+mod assets;
+mod canary;
 mod config;
+mod cookies;
+mod cors;
 mod engine;
 mod error;
+mod extension;
+mod history;
 mod middleware;
+mod redirect;
 mod router;
+mod tenant;
+mod tls;
 
 #[derive(Clone)]
 pub struct AppState {
-    routers: DashMap<String, SwappableAppRouter>,
+    tenants: SwappableTenants,
+    extensions: Extensions,
+    middlewares: Middlewares,
 }
 impl AppState {
-    pub fn new(routes: DashMap<String, SwappableAppRouter>) -> Self {
-        Self { routers: routes }
+    pub fn new(tenants: Tenants) -> Self {
+        Self {
+            tenants: SwappableTenants::new(tenants),
+            extensions: Extensions::default(),
+            middlewares: Middlewares::default(),
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct TenentRouter {
-    host: String,
-    router: SwappableAppRouter,
+/// Builder for a dino server, for embedders which need more than `start_server`.
+pub struct DinoServer {
+    port: u16,
+    routers: Vec<TenentRouter>,
+    fallback: Option<SwappableAppRouter>,
+    swappable: Option<SwappableTenants>,
+    /// port and token of the admin api
+    admin: Option<(u16, String)>,
+    admin_ip: IpAddr,
+    history: Option<DeployHistory>,
+    tls: Vec<TlsListener>,
+    extensions: Vec<Arc<dyn Extension>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
 }
 
-impl TenentRouter {
-    pub fn new(host: impl Into<String>, router: SwappableAppRouter) -> Self {
+impl DinoServer {
+    pub fn new(port: u16) -> Self {
         Self {
-            host: host.into(),
-            router,
+            port,
+            routers: Vec::new(),
+            fallback: None,
+            swappable: None,
+            admin: None,
+            admin_ip: Ipv4Addr::LOCALHOST.into(),
+            history: None,
+            tls: Vec::new(),
+            extensions: Vec::new(),
+            middlewares: Vec::new(),
         }
     }
+
+    pub fn tenant(mut self, router: TenentRouter) -> Self {
+        self.routers.push(router);
+        self
+    }
+
+    pub fn tenants(mut self, routers: impl IntoIterator<Item = TenentRouter>) -> Self {
+        self.routers.extend(routers);
+        self
+    }
+
+    /// serve requests whose host matches no tenant with this project
+    pub fn default_tenant(mut self, router: SwappableAppRouter) -> Self {
+        self.fallback = Some(router);
+        self
+    }
+
+    /// serve a tenant set which can be swapped while the server runs, instead of the
+    /// tenants given with `tenant` and `default_tenant`
+    pub fn swappable_tenants(mut self, tenants: SwappableTenants) -> Self {
+        self.swappable = Some(tenants);
+        self
+    }
+
+    /// serve the admin api to list, deploy and remove tenants on its own port of 127.0.0.1,
+    /// see `admin_ip`, requests need an `Authorization: Bearer <token>` header
+    pub fn admin(mut self, port: u16, token: impl Into<String>) -> Self {
+        self.admin = Some((port, token.into()));
+        self
+    }
+
+    /// address the admin api listens on, only local clients reach it by default
+    pub fn admin_ip(mut self, ip: impl Into<IpAddr>) -> Self {
+        self.admin_ip = ip.into();
+        self
+    }
+
+    /// keep the versions deployed through the admin api in a directory, to roll back
+    /// to them after a restart as well
+    pub fn history(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.history = Some(DeployHistory::new(dir));
+        self
+    }
+
+    /// serve https on another port as well
+    pub fn tls(mut self, listener: TlsListener) -> Self {
+        self.tls.push(listener);
+        self
+    }
+
+    /// make an extension available to the projects listing it in their config
+    pub fn extension(mut self, extension: impl Extension) -> Self {
+        self.extensions.push(Arc::new(extension));
+        self
+    }
+
+    /// make a rust middleware available to routes under its name, next to the built-in ones
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    pub async fn serve(self) -> anyhow::Result<()> {
+        let extensions = Extensions::new(self.extensions);
+        let middlewares = Middlewares::new(self.middlewares);
+        let tenants = match self.swappable {
+            Some(tenants) => {
+                if !self.routers.is_empty() || self.fallback.is_some() {
+                    bail!("tenants can't be added to a server serving swappable tenants");
+                }
+                tenants
+            }
+            None => SwappableTenants::new(Tenants::try_new(self.routers, self.fallback)?),
+        };
+        for router in tenants.load().routers() {
+            let loaded = router.load();
+            extensions.resolve(&loaded.extensions)?;
+            middlewares.resolve(&loaded.middlewares)?;
+        }
+
+        let addr = format!("0.0.0.0:{}", self.port);
+        let listener = TcpListener::bind(addr).await?;
+        info!("listening on {}", listener.local_addr()?);
+
+        let state = AppState {
+            tenants,
+            extensions,
+            middlewares,
+        };
+        let admin = match self.admin {
+            Some((_, token)) if token.is_empty() => bail!("the admin api needs a token"),
+            Some((port, token)) => {
+                let listener = TcpListener::bind((self.admin_ip, port)).await?;
+                info!("admin api listening on {}", listener.local_addr()?);
+                let admin = admin::router(token, state.clone(), self.history);
+                Some((listener, admin))
+            }
+            None => None,
+        };
+        let mut tls = Vec::new();
+        for listener in self.tls {
+            tls.push(listener.bind().await?);
+        }
+        let app = app(state);
+
+        // the first listener failing stops the server
+        let mut servers = JoinSet::new();
+        for bound in tls {
+            servers.spawn(tls::serve(bound, app.clone()));
+        }
+        if let Some((listener, admin)) = admin {
+            let server = axum::serve(listener, admin).into_future();
+            servers.spawn(async move { Ok(server.await?) });
+        }
+        let server = axum::serve(listener, app.into_make_service()).into_future();
+        servers.spawn(async move { Ok(server.await?) });
+        while let Some(ret) = servers.join_next().await {
+            ret??;
+        }
+        Ok(())
+    }
 }
 
 pub async fn start_server(port: u16, routers: Vec<TenentRouter>) -> anyhow::Result<()> {
-    let addr = format!("0.0.0.0:{port}");
-    let listener = TcpListener::bind(addr).await?;
-    info!("listening on {}", listener.local_addr()?);
+    DinoServer::new(port).tenants(routers).serve().await
+}
 
-    let map = DashMap::new();
-    for TenentRouter { host, router } in routers {
-        map.insert(host, router);
-    }
+/// body size limit of routes without `max_body_size`, the same as axum's default
+const DEFAULT_MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// the version of the project which answered, to tell deploys and rollbacks apart,
+/// requests may send it to be served by the stable or the canary version
+const VERSION_HEADER: &str = "x-dino-version";
 
-    let state = AppState::new(map);
-    let app = Router::new()
+/// the routes of the tenants, served on every listener but the admin one
+fn app(state: AppState) -> Router {
+    Router::new()
         .route("/*path", any(handler))
         .layer(ServerTimeLayer)
-        .with_state(state);
-    axum::serve(listener, app.into_make_service()).await?;
-    Ok(())
+        .with_state(state)
 }
 
 async fn handler(
@@ -73,33 +253,171 @@ async fn handler(
     parts: Parts,
     Host(host): Host,
     Query(query): Query<HashMap<String, String>>,
-    body: Option<Bytes>,
-) -> Result<impl IntoResponse, AppError> {
-    let router = get_router_by_host(host, state)?;
-    let matched = router.match_it(parts.method.clone(), parts.uri.path())?;
-    let req = assemble_req(&matched, &parts, query, body)?;
-    let handler = matched.value;
-    let worker = JsWorker::try_new(&router.code)?;
-    let res = worker.run(handler, req)?;
-    Ok(Response::from(res))
+    body: Body,
+) -> Response {
+    let (tenant, base) = match get_router_by_host(host, parts.uri.path(), &state) {
+        Ok(tenant) => tenant,
+        Err(e) => return e.into_response(),
+    };
+    let (router, pin) = tenant.pick(&parts.headers);
+    let started = Instant::now();
+    let mut res = serve_tenant(&state, &router, &base, parts, query, body)
+        .await
+        .unwrap_or_else(|e| e.into_response());
+    router.stats.record(res.status(), started.elapsed());
+    if let Ok(version) = HeaderValue::from_str(&router.hash) {
+        res.headers_mut().insert(VERSION_HEADER, version);
+    }
+    if pin {
+        let path = if base.is_empty() { "/" } else { &base };
+        let cookie = format!(
+            "{}={}; Path={}; HttpOnly; SameSite=Lax",
+            canary::STICKY_COOKIE,
+            router.hash,
+            path
+        );
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            res.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+    res
+}
+
+async fn serve_tenant(
+    state: &AppState,
+    router: &AppRouter,
+    base: &str,
+    parts: Parts,
+    query: HashMap<String, String>,
+    body: Body,
+) -> Result<Response, AppError> {
+    let path = match &parts.uri.path()[base.len()..] {
+        "" => "/",
+        path => path,
+    };
+    if let Some(res) = router.redirects.redirect(path, parts.uri.query(), base) {
+        return Ok(res);
+    }
+    let path = router
+        .redirects
+        .rewrite(path)
+        .unwrap_or_else(|| path.to_string());
+    if let Some(res) = cors::preflight(router, &parts, &path) {
+        return Ok(res);
+    }
+    if let Some(public) = &router.public {
+        if let Some(mut res) = public.serve(&parts.method, &path, &parts.headers).await {
+            cors::apply(router.cors.as_ref(), &parts.headers, &mut res);
+            return Ok(res);
+        }
+    }
+    if parts.method == Method::OPTIONS {
+        if let Some(res) = router.options(&path) {
+            return Ok(res);
+        }
+    }
+    // error responses get the cors headers too, or browsers only see a failed request
+    let (cors, res) = match router.match_it(parts.method.clone(), &path) {
+        Ok(matched) => {
+            let res = run_route(state, router, base, &matched, &parts, query, body).await;
+            (matched.value.cors.as_ref(), res)
+        }
+        Err(e) => (router.cors.as_ref(), Err(e)),
+    };
+    let mut res = res.unwrap_or_else(IntoResponse::into_response);
+    cors::apply(cors, &parts.headers, &mut res);
+    // HEAD is answered like GET, hyper drops the body but keeps its content-length
+    Ok(res)
+}
+
+/// run the js handler of a matched route within its body size and time limits
+async fn run_route(
+    state: &AppState,
+    router: &AppRouter,
+    base: &str,
+    matched: &Match<'_, '_, &RouteHandler>,
+    parts: &Parts,
+    query: HashMap<String, String>,
+    body: Body,
+) -> Result<Response, AppError> {
+    let route = matched.value;
+    let started = Instant::now();
+    let run = async {
+        let limit = route.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE);
+        let body = read_body(&parts.headers, body, limit).await?;
+        let mut req = assemble_req(
+            matched,
+            parts,
+            query,
+            Some(body),
+            router.cookie_key.as_ref(),
+        )?;
+        req.base_path = base.to_string();
+        let chain = state.middlewares.resolve(&route.middlewares)?;
+        let mut extensions = state.extensions.resolve(&router.extensions)?;
+        if let Some(public) = &router.public {
+            extensions.push(public.clone());
+        }
+        // the deadline covers evaluating the bundle, which may not yield to the timeout below
+        let deadline = route.timeout.map(|timeout| started + timeout);
+        let worker = JsWorker::try_new(&router.code, &extensions, deadline).await?;
+        Ok::<_, AppError>(run_chain(&worker, &chain, &route.handler, req).await?)
+    };
+    let res = match route.timeout {
+        // js interrupted at the deadline fails with its own error, report it as the timeout
+        Some(timeout) => tokio::time::timeout(timeout, run)
+            .await
+            .unwrap_or(Err(AppError::RequestTimeout(timeout)))
+            .map_err(|e| {
+                if started.elapsed() >= timeout {
+                    AppError::RequestTimeout(timeout)
+                } else {
+                    e
+                }
+            })?,
+        None => run.await?,
+    };
+    res.into_response(router.cookie_key.as_ref())
+}
+
+/// read the whole body, failing as soon as it is known to exceed the limit
+async fn read_body(headers: &HeaderMap, body: Body, limit: usize) -> Result<Bytes, AppError> {
+    let length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<usize>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return Err(AppError::PayloadTooLarge(limit));
+    }
+    axum::body::to_bytes(body, limit).await.map_err(|e| {
+        let e = e.into_inner();
+        if e.is::<LengthLimitError>() {
+            AppError::PayloadTooLarge(limit)
+        } else {
+            AppError::Anyhow(anyhow::anyhow!(e))
+        }
+    })
 }
 
-fn get_router_by_host(mut host: String, state: AppState) -> Result<AppRouter, AppError> {
-    let _ = host.split_off(host.find(':').unwrap_or(host.len()));
+/// the tenant serving a request along with the path prefix it is mounted at
+fn get_router_by_host(
+    host: String,
+    path: &str,
+    state: &AppState,
+) -> Result<(SwappableAppRouter, String), AppError> {
     info!("host: {:?}", host);
-    let router: AppRouter = state
-        .routers
-        .get(&host)
-        .ok_or(AppError::HostNotFound(host))?
-        .load();
-    Ok(router)
+    let tenants = state.tenants.load();
+    let (router, base) = tenants
+        .get(&host, path)
+        .ok_or(AppError::HostNotFound(host))?;
+    Ok((router.clone(), base.to_string()))
 }
 
 fn assemble_req(
-    matched: &Match<&str>,
+    matched: &Match<&RouteHandler>,
     parts: &Parts,
     query: HashMap<String, String>,
     body: Option<Bytes>,
+    cookie_key: Option<&Key>,
 ) -> Result<Req, AppError> {
     let params: HashMap<String, String> = matched
         .params
@@ -109,8 +427,18 @@ fn assemble_req(
     let headers = parts
         .headers
         .iter()
-        .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
+        // values are bytes, non-ascii ones are decoded as utf-8 like browsers send them
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned(),
+            )
+        })
         .collect();
+    let cookies = parse_cookies(&parts.headers);
+    let signed_cookies = cookie_key
+        .map(|key| verify_cookies(&cookies, key))
+        .unwrap_or_default();
     // let body = body.and_then(|v| String::from_utf8(v.into()).ok());
     let body = body.and_then(|v| String::from_utf8(v.into()).ok());
 
@@ -120,7 +448,170 @@ fn assemble_req(
         .query(query)
         .params(params)
         .headers(headers)
+        .cookies(cookies)
+        .signed_cookies(signed_cookies)
         .body(body)
         .build();
     Ok(req)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{
+        header::{ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN, VARY},
+        StatusCode,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn read_body_should_enforce_limit() {
+        let headers = HeaderMap::new();
+        let body = read_body(&headers, Body::from("hello"), 5).await.unwrap();
+        assert_eq!(body, "hello");
+
+        let ret = read_body(&headers, Body::from("hello!"), 5).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(5))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, 100.into());
+        let ret = read_body(&headers, Body::empty(), 5).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(5))));
+    }
+
+    #[tokio::test]
+    async fn error_responses_should_have_cors_headers() -> anyhow::Result<()> {
+        let config = ProjectConfig::parse(
+            r#"
+name: p
+cors: { origins: [https://app.test] }
+routes:
+  /fail:
+    - method: POST
+      handler: fail
+      max_body_size: 4b
+"#,
+        )?;
+        let code =
+            "(function(){ function fail(){ throw new Error('boom'); } return { fail }; })();";
+        let router = SwappableAppRouter::try_new(code, config)?.load();
+        let state = AppState::new(Tenants::default());
+        let call = |method: Method, path: &str, body: &'static str| {
+            let (parts, body) = axum::http::Request::builder()
+                .method(method)
+                .uri(path)
+                .header(ORIGIN, "https://app.test")
+                .body(Body::from(body))
+                .unwrap()
+                .into_parts();
+            serve_tenant(&state, &router, "", parts, HashMap::new(), body)
+        };
+
+        for (method, path, body, status) in [
+            (Method::POST, "/fail", "", StatusCode::INTERNAL_SERVER_ERROR),
+            (
+                Method::POST,
+                "/fail",
+                "too large",
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (Method::GET, "/fail", "", StatusCode::METHOD_NOT_ALLOWED),
+            (Method::GET, "/missing", "", StatusCode::NOT_FOUND),
+        ] {
+            let res = call(method, path, body).await?;
+            assert_eq!(res.status(), status, "{path}");
+            assert_eq!(
+                res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+                "https://app.test"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handlers_should_get_decoded_headers() -> anyhow::Result<()> {
+        let config = ProjectConfig::parse(
+            "{ name: p, routes: { /hello: [{ method: GET, handler: hello }] } }",
+        )?;
+        let code = r#"(function(){
+            function hello(req){ return `${req.headers["x-name"]} ${req.basePath}`; }
+            return { hello };
+        })();"#;
+        let router = SwappableAppRouter::try_new(code, config)?.load();
+        let state = AppState::new(Tenants::default());
+        let (parts, body) = axum::http::Request::builder()
+            .uri("/app/hello")
+            .header("x-name", HeaderValue::from_bytes("café".as_bytes())?)
+            .body(Body::empty())?
+            .into_parts();
+        let res = serve_tenant(&state, &router, "/app", parts, HashMap::new(), body).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body, "café /app");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn public_files_should_have_cors_headers() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("app.js"), "console.log(1)")?;
+        let config = ProjectConfig::parse(&format!(
+            "{{ name: p, public: {:?}, cors: {{ origins: [https://app.test] }}, routes: {{}} }}",
+            dir.path()
+        ))?;
+        let router = SwappableAppRouter::try_new("(function(){ return {}; })();", config)?.load();
+        let state = AppState::new(Tenants::default());
+        for (origin, allowed) in [("https://app.test", true), ("https://evil.test", false)] {
+            let (parts, body) = axum::http::Request::builder()
+                .uri("/app.js")
+                .header(ORIGIN, origin)
+                .body(Body::empty())?
+                .into_parts();
+            let res = serve_tenant(&state, &router, "", parts, HashMap::new(), body).await?;
+            assert_eq!(res.status(), StatusCode::OK);
+            let vary: Vec<_> = res.headers().get_all(VARY).iter().collect();
+            assert!(vary.contains(&&HeaderValue::from_static("origin")));
+            assert_eq!(
+                res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN),
+                allowed
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn head_should_have_the_headers_of_get() -> anyhow::Result<()> {
+        let config = ProjectConfig::parse(
+            "{ name: p, routes: { /hello: [{ method: GET, handler: hello }] } }",
+        )?;
+        let code = "(function(){ function hello(){ return 'hello world'; } return { hello }; })();";
+        let router = SwappableAppRouter::try_new(code, config)?;
+        let tenants = Tenants::try_new(vec![TenentRouter::new("p.test", router)], None)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(axum::serve(listener, app(AppState::new(tenants))).into_future());
+
+        let call = |method: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await?;
+            let req =
+                format!("{method} /hello HTTP/1.1\r\nhost: p.test\r\nconnection: close\r\n\r\n");
+            stream.write_all(req.as_bytes()).await?;
+            let mut res = String::new();
+            stream.read_to_string(&mut res).await?;
+            anyhow::Ok(res)
+        };
+        let content_length = |res: &str| {
+            res.lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .map(str::to_string)
+        };
+        let get = call("GET").await?;
+        let head = call("HEAD").await?;
+        assert!(get.ends_with("hello world"), "{get}");
+        assert!(head.ends_with("\r\n\r\n"), "{head}");
+        assert_eq!(content_length(&head), Some("11".to_string()));
+        assert_eq!(content_length(&head), content_length(&get));
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use axum::http::header::CACHE_CONTROL;
+use uuid::Uuid;
+
+use crate::engine::{Req, Res};
+
+use super::{chain::Middleware, REQUEST_ID_HEADER};
+
+/// keep the `x-request-id` of the request or generate one, and echo it on the response
+pub struct RequestId;
+
+/// mark the response as not cacheable
+pub struct NoCache;
+
+impl Middleware for RequestId {
+    fn name(&self) -> &str {
+        "request-id"
+    }
+
+    fn before(&self, req: &mut Req) -> Option<Res> {
+        req.headers
+            .entry(REQUEST_ID_HEADER.to_string())
+            .or_insert_with(|| Uuid::new_v4().to_string());
+        None
+    }
+
+    fn after(&self, req: &Req, res: &mut Res) {
+        if let Some(id) = req.headers.get(REQUEST_ID_HEADER) {
+            res.headers
+                .entry(REQUEST_ID_HEADER.to_string())
+                .or_insert_with(|| id.clone());
+        }
+    }
+}
+
+impl Middleware for NoCache {
+    fn name(&self) -> &str {
+        "no-cache"
+    }
+
+    fn after(&self, _req: &Req, res: &mut Res) {
+        res.headers
+            .insert(CACHE_CONTROL.to_string(), "no-store".to_string());
+    }
+}
@@ -0,0 +1,200 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    config::MiddlewareConfig,
+    engine::{JsWorker, Req, Res},
+    error::AppError,
+};
+
+use super::builtin::{NoCache, RequestId};
+
+/// A rust middleware which routes can reference by name in `middlewares`.
+pub trait Middleware: Send + Sync + 'static {
+    /// name used to reference the middleware in the project config
+    fn name(&self) -> &str;
+
+    /// inspect or modify the request, returning a response skips the handler
+    fn before(&self, _req: &mut Req) -> Option<Res> {
+        None
+    }
+
+    /// inspect or modify the response on the way out
+    fn after(&self, _req: &Req, _res: &mut Res) {}
+}
+
+/// a resolved middleware in a route chain
+pub enum Step {
+    Rust(Arc<dyn Middleware>),
+    Before(String),
+    After(String),
+}
+
+#[derive(Clone)]
+pub struct Middlewares(Arc<HashMap<String, Arc<dyn Middleware>>>);
+
+impl Middlewares {
+    /// the built-in middlewares followed by the given ones, later names win
+    pub fn new(middlewares: impl IntoIterator<Item = Arc<dyn Middleware>>) -> Self {
+        let builtin: [Arc<dyn Middleware>; 2] = [Arc::new(RequestId), Arc::new(NoCache)];
+        let map = builtin
+            .into_iter()
+            .chain(middlewares)
+            .map(|m| (m.name().to_string(), m))
+            .collect();
+        Self(Arc::new(map))
+    }
+
+    pub fn resolve(&self, configs: &[MiddlewareConfig]) -> Result<Vec<Step>, AppError> {
+        configs
+            .iter()
+            .map(|config| match config {
+                MiddlewareConfig::Named(name) => self
+                    .0
+                    .get(name)
+                    .cloned()
+                    .map(Step::Rust)
+                    .ok_or_else(|| AppError::MiddlewareNotFound(name.clone())),
+                MiddlewareConfig::Before { before } => Ok(Step::Before(before.clone())),
+                MiddlewareConfig::After { after } => Ok(Step::After(after.clone())),
+            })
+            .collect()
+    }
+}
+
+impl Default for Middlewares {
+    fn default() -> Self {
+        Self::new([])
+    }
+}
+
+/// run the `before` steps in order, then the handler, then the `after` steps in reverse.
+/// When a step short-circuits, only the steps already entered see the response.
+pub async fn run_chain(
+    worker: &JsWorker,
+    chain: &[Step],
+    handler: &str,
+    mut req: Req,
+) -> anyhow::Result<Res> {
+    let mut entered = 0;
+    let mut short_circuit = None;
+    for step in chain {
+        entered += 1;
+        let ret = match step {
+            Step::Rust(m) => m.before(&mut req),
+            Step::Before(name) => {
+                let (modified, ret) = worker.run_before(name, req).await?;
+                req = modified;
+                ret
+            }
+            Step::After(_) => None,
+        };
+        if ret.is_some() {
+            short_circuit = ret;
+            break;
+        }
+    }
+
+    let mut res = match short_circuit {
+        Some(res) => res,
+        None => worker.run(handler, req.clone()).await?,
+    };
+    for step in chain[..entered].iter().rev() {
+        match step {
+            Step::Rust(m) => m.after(&req, &mut res),
+            Step::After(name) => res = worker.run_after(name, req.clone(), res).await?,
+            Step::Before(_) => {}
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Deny;
+
+    impl Middleware for Deny {
+        fn name(&self) -> &str {
+            "deny"
+        }
+
+        fn before(&self, _req: &mut Req) -> Option<Res> {
+            Some(Res::new(403))
+        }
+    }
+
+    const CODE: &str = r#"
+    (function(){
+        function auth(req) {
+            if (!req.headers.authorization) return new Response("unauthorized", { status: 401 });
+            req.params.user = req.headers.authorization;
+        }
+        function audit(req, res) {
+            res.headers["x-audited"] = "true";
+        }
+        function wrap(req, res) {
+            return { status: res.status, headers: res.headers, body: `[${res.body}]` };
+        }
+        function hello(req) { return `hello ${req.params.user}`; }
+        return { auth, audit, wrap, hello };
+    })();
+    "#;
+
+    fn resolve(configs: &str) -> Vec<Step> {
+        let configs: Vec<MiddlewareConfig> = serde_yaml::from_str(configs).unwrap();
+        Middlewares::new([Arc::new(Deny) as Arc<dyn Middleware>])
+            .resolve(&configs)
+            .unwrap()
+    }
+
+    fn req(authorization: Option<&str>) -> Req {
+        let headers = authorization
+            .map(|v| HashMap::from([("authorization".to_string(), v.to_string())]))
+            .unwrap_or_default();
+        Req::builder()
+            .method("GET")
+            .url("/")
+            .headers(headers)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn chain_should_wrap_handler() {
+        let worker = JsWorker::try_new(CODE, &[], None).await.unwrap();
+        let chain = resolve("[{ after: wrap }, { before: auth }, { after: audit }]");
+
+        let res = run_chain(&worker, &chain, "hello", req(Some("dino")))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body.as_deref(), Some("[hello dino]"));
+        assert_eq!(res.headers["x-audited"], "true");
+    }
+
+    #[tokio::test]
+    async fn chain_should_short_circuit() {
+        let worker = JsWorker::try_new(CODE, &[], None).await.unwrap();
+        let chain = resolve("[{ after: wrap }, { before: auth }, { after: audit }]");
+        let res = run_chain(&worker, &chain, "hello", req(None))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 401);
+        assert_eq!(res.body.as_deref(), Some("[unauthorized]"));
+        assert!(!res.headers.contains_key("x-audited"));
+
+        let chain = resolve("[request-id, deny, { before: auth }]");
+        let res = run_chain(&worker, &chain, "hello", req(Some("dino")))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 403);
+        assert!(res.headers.contains_key("x-request-id"));
+    }
+
+    #[test]
+    fn unknown_middleware_should_fail() {
+        let configs = vec![MiddlewareConfig::Named("rate-limit".to_string())];
+        let ret = Middlewares::default().resolve(&configs);
+        assert!(matches!(ret, Err(AppError::MiddlewareNotFound(name)) if name == "rate-limit"));
+    }
+}
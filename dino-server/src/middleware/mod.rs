@@ -1,6 +1,9 @@
+mod builtin;
+mod chain;
 mod server_time;
 
 const SERVER_TIME_HEADER: &str = "x-server-time";
 const REQUEST_ID_HEADER: &str = "x-request-id";
 
+pub use chain::{run_chain, Middleware, Middlewares};
 pub use server_time::ServerTimeLayer;
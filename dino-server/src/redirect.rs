@@ -0,0 +1,236 @@
+use anyhow::{bail, Context as _};
+use axum::{
+    body::Body,
+    http::{header::LOCATION, StatusCode},
+    response::Response,
+};
+use matchit::{Params, Router};
+
+use crate::config::{RedirectConfig, RewriteConfig};
+
+/// The redirect and rewrite rules of a project, checked before static files and routes.
+pub struct Redirects {
+    redirects: Router<RedirectConfig>,
+    rewrites: Router<String>,
+}
+
+impl Redirects {
+    pub fn try_new(
+        redirects: &[RedirectConfig],
+        rewrites: &[RewriteConfig],
+    ) -> anyhow::Result<Self> {
+        let mut redirect_router = Router::new();
+        for redirect in redirects {
+            if !StatusCode::from_u16(redirect.status).is_ok_and(|s| s.is_redirection()) {
+                bail!(
+                    "redirect {} has status {} which is not a redirection",
+                    redirect.from,
+                    redirect.status
+                );
+            }
+            check_placeholders(&redirect.from, &redirect.to)?;
+            redirect_router
+                .insert(redirect.from.clone(), redirect.clone())
+                .with_context(|| format!("invalid redirect {}", redirect.from))?;
+        }
+
+        let mut rewrite_router = Router::new();
+        for rewrite in rewrites {
+            if !rewrite.to.starts_with('/') || rewrite.to.contains('?') {
+                bail!("rewrite target {} must be a path without query", rewrite.to);
+            }
+            check_placeholders(&rewrite.from, &rewrite.to)?;
+            rewrite_router
+                .insert(rewrite.from.clone(), rewrite.to.clone())
+                .with_context(|| format!("invalid rewrite {}", rewrite.from))?;
+        }
+
+        Ok(Self {
+            redirects: redirect_router,
+            rewrites: rewrite_router,
+        })
+    }
+
+    /// the redirect response for a path, if a rule matches, targets within the project
+    /// are kept below the base path the project is mounted at
+    pub fn redirect(&self, path: &str, query: Option<&str>, base: &str) -> Option<Response> {
+        let matched = self.redirects.at(path).ok()?;
+        let rule = matched.value;
+        let mut location = expand(&rule.to, &matched.params);
+        if location.starts_with('/') && !location.starts_with("//") {
+            location.insert_str(0, base);
+        }
+        if let Some(query) = query.filter(|q| rule.preserve_query && !q.is_empty()) {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+        Response::builder()
+            .status(rule.status)
+            .header(LOCATION, location)
+            .body(Body::empty())
+            .ok()
+    }
+
+    /// the path a request should be routed as, if a rule matches
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        let matched = self.rewrites.at(path).ok()?;
+        Some(expand(matched.value, &matched.params))
+    }
+}
+
+impl Default for Redirects {
+    fn default() -> Self {
+        Self {
+            redirects: Router::new(),
+            rewrites: Router::new(),
+        }
+    }
+}
+
+/// the `{name}` or `{*name}` placeholders of a matchit path
+fn placeholders(path: &str) -> impl Iterator<Item = &str> {
+    path.split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}'))
+        .map(|(name, _)| name.trim_start_matches('*'))
+}
+
+fn check_placeholders(from: &str, to: &str) -> anyhow::Result<()> {
+    let params: Vec<_> = placeholders(from).collect();
+    if let Some(missing) = placeholders(to).find(|name| !params.contains(name)) {
+        bail!("target {to} uses {{{missing}}} which is not a parameter of {from}");
+    }
+    Ok(())
+}
+
+/// fill the placeholders of a target with the matched params, a path stays a path when
+/// a param starts with slashes, `//evil.com` would point browsers at another host
+fn expand(target: &str, params: &Params) -> String {
+    let mut ret = String::with_capacity(target.len());
+    let mut rest = target;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        ret.push_str(&rest[..start]);
+        let name = rest[start + 1..start + len].trim_start_matches('*');
+        ret.push_str(params.get(name).unwrap_or_default());
+        rest = &rest[start + len + 1..];
+    }
+    ret.push_str(rest);
+    if target.starts_with('/') && !target.starts_with("//") {
+        let path = ret.trim_start_matches(['/', '\\']);
+        return format!("/{path}");
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirects(yaml: &str) -> anyhow::Result<Redirects> {
+        #[derive(serde::Deserialize)]
+        struct Rules {
+            #[serde(default)]
+            redirects: Vec<RedirectConfig>,
+            #[serde(default)]
+            rewrites: Vec<RewriteConfig>,
+        }
+        let rules: Rules = serde_yaml::from_str(yaml)?;
+        Redirects::try_new(&rules.redirects, &rules.rewrites)
+    }
+
+    #[test]
+    fn redirect_should_work() -> anyhow::Result<()> {
+        let rules = redirects(
+            r#"
+            redirects:
+              - from: /old/{id}
+                to: /new/{id}
+              - from: /docs/{*path}
+                to: https://docs.example.com/{path}?ref=dino
+                status: 302
+              - from: /tmp
+                to: /
+                preserve_query: false
+            "#,
+        )?;
+
+        let res = rules.redirect("/old/42", Some("page=2"), "").unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()[LOCATION], "/new/42?page=2");
+
+        let res = rules
+            .redirect("/docs/guide/intro", Some("a=1"), "/app")
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(
+            res.headers()[LOCATION],
+            "https://docs.example.com/guide/intro?ref=dino&a=1"
+        );
+
+        let res = rules.redirect("/tmp", Some("a=1"), "").unwrap();
+        assert_eq!(res.headers()[LOCATION], "/");
+        let res = rules.redirect("/old/42", None, "/app").unwrap();
+        assert_eq!(res.headers()[LOCATION], "/app/new/42");
+        assert!(rules.redirect("/new/42", None, "").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn params_should_not_redirect_to_other_hosts() -> anyhow::Result<()> {
+        let rules = redirects(
+            r#"
+            redirects:
+              - from: /old/{*path}
+                to: /{path}
+              - from: /cdn/{*path}
+                to: //cdn.example.com/{path}
+            rewrites:
+              - from: /r/{*path}
+                to: /{path}
+            "#,
+        )?;
+        let res = rules.redirect("/old//evil.com", None, "").unwrap();
+        assert_eq!(res.headers()[LOCATION], "/evil.com");
+        let res = rules.redirect("/old/\\evil.com", None, "").unwrap();
+        assert_eq!(res.headers()[LOCATION], "/evil.com");
+        let res = rules.redirect("/old//evil.com", None, "/app").unwrap();
+        assert_eq!(res.headers()[LOCATION], "/app/evil.com");
+        let res = rules.redirect("/cdn/a.js", None, "/app").unwrap();
+        assert_eq!(res.headers()[LOCATION], "//cdn.example.com/a.js");
+        assert_eq!(rules.rewrite("/r//evil.com").as_deref(), Some("/evil.com"));
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_should_work() -> anyhow::Result<()> {
+        let rules = redirects(
+            r#"
+            rewrites:
+              - from: /blog/{*slug}
+                to: /api/posts/{slug}
+            "#,
+        )?;
+        assert_eq!(
+            rules.rewrite("/blog/2024/hello").as_deref(),
+            Some("/api/posts/2024/hello")
+        );
+        assert!(rules.rewrite("/api/posts/1").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_rules_should_fail() {
+        let cases = [
+            "redirects: [{ from: /a/{id}, to: /b/{name} }]",
+            "redirects: [{ from: /a, to: /b, status: 200 }]",
+            "rewrites: [{ from: /a, to: https://example.com }]",
+            "rewrites: [{ from: /a, to: /b }, { from: /a, to: /c }]",
+        ];
+        for case in cases {
+            assert!(redirects(case).is_err(), "{case}");
+        }
+    }
+}
@@ -1,78 +1,459 @@
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
-use arc_swap::ArcSwap;
-use axum::http::Method;
+use anyhow::{bail, Context as _};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use axum::{
+    body::Body,
+    http::{header::ALLOW, HeaderMap, Method, StatusCode},
+    response::Response,
+};
+use cookie::Key;
 use matchit::{Match, Router};
 
-use crate::{config::ProjectRoutes, error::AppError};
+use crate::{
+    assets::PublicDir,
+    canary::{self, Canary, VersionStats},
+    config::{CorsConfig, MiddlewareConfig, ProjectConfig, RouteMethod},
+    cookies::cookie_key,
+    engine::exports,
+    error::AppError,
+    extension::{Extension, Extensions},
+    redirect::Redirects,
+};
 
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct MethodRoute {
-    get: Option<String>, // handler name in js code
-    head: Option<String>,
-    delete: Option<String>,
-    options: Option<String>,
-    patch: Option<String>,
-    post: Option<String>,
-    put: Option<String>,
-    trace: Option<String>,
-    connect: Option<String>,
+    get: Option<RouteHandler>,
+    head: Option<RouteHandler>,
+    delete: Option<RouteHandler>,
+    options: Option<RouteHandler>,
+    patch: Option<RouteHandler>,
+    post: Option<RouteHandler>,
+    put: Option<RouteHandler>,
+    trace: Option<RouteHandler>,
+    connect: Option<RouteHandler>,
+    /// extension methods like `PROPFIND`
+    extensions: HashMap<Method, RouteHandler>,
+    /// fallback for methods without their own handler
+    any: Option<RouteHandler>,
 }
 
+impl MethodRoute {
+    /// set the handler of a method, `false` if the method already has one
+    pub fn insert(&mut self, method: &RouteMethod, handler: RouteHandler) -> bool {
+        let slot = match method {
+            RouteMethod::Any => &mut self.any,
+            RouteMethod::Method(m) => match *m {
+                Method::GET => &mut self.get,
+                Method::HEAD => &mut self.head,
+                Method::DELETE => &mut self.delete,
+                Method::OPTIONS => &mut self.options,
+                Method::PATCH => &mut self.patch,
+                Method::POST => &mut self.post,
+                Method::PUT => &mut self.put,
+                Method::TRACE => &mut self.trace,
+                Method::CONNECT => &mut self.connect,
+                _ => {
+                    if self.extensions.contains_key(m) {
+                        return false;
+                    }
+                    self.extensions.insert(m.clone(), handler);
+                    return true;
+                }
+            },
+        };
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(handler);
+        true
+    }
+
+    /// the handler of a method, HEAD falls back to GET and every method to the `ANY` handler
+    pub fn get(&self, method: &Method) -> Option<&RouteHandler> {
+        let handler = match *method {
+            Method::GET => self.get.as_ref(),
+            Method::HEAD => self.head.as_ref().or(self.get.as_ref()),
+            Method::DELETE => self.delete.as_ref(),
+            Method::OPTIONS => self.options.as_ref(),
+            Method::PATCH => self.patch.as_ref(),
+            Method::POST => self.post.as_ref(),
+            Method::PUT => self.put.as_ref(),
+            Method::TRACE => self.trace.as_ref(),
+            Method::CONNECT => self.connect.as_ref(),
+            _ => self.extensions.get(method),
+        };
+        handler.or(self.any.as_ref())
+    }
+
+    /// the `Allow` header value, OPTIONS is always allowed as it is answered by the router
+    pub fn allow(&self) -> String {
+        let standard = [
+            (Method::GET, self.get.is_some()),
+            (Method::HEAD, self.head.is_some() || self.get.is_some()),
+            (Method::POST, self.post.is_some()),
+            (Method::PUT, self.put.is_some()),
+            (Method::PATCH, self.patch.is_some()),
+            (Method::DELETE, self.delete.is_some()),
+            (Method::OPTIONS, true),
+            (Method::TRACE, self.trace.is_some()),
+            (Method::CONNECT, self.connect.is_some()),
+        ];
+        let mut extensions: Vec<_> = self.extensions.keys().map(Method::as_str).collect();
+        extensions.sort_unstable();
+        standard
+            .iter()
+            .filter(|(_, defined)| *defined)
+            .map(|(method, _)| method.as_str())
+            .chain(extensions)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RouteHandler {
+    /// handler name in js code
+    pub handler: String,
+    /// the full middleware chain: project, then matching groups, then the route's own
+    pub middlewares: Vec<MiddlewareConfig>,
+    /// the route's own cors policy or the project's
+    pub cors: Option<CorsConfig>,
+    /// the route's own limits or the project's
+    pub timeout: Option<Duration>,
+    pub max_body_size: Option<usize>,
+}
+
+/// versions kept to roll back to, per tenant
+pub(crate) const HISTORY_LIMIT: usize = 10;
+
 #[derive(Clone)]
 pub struct SwappableAppRouter {
     pub inner: Arc<ArcSwap<AppRouterInner>>,
+    /// the versions served before the current one, newest first
+    history: Arc<Mutex<VecDeque<Arc<AppRouterInner>>>>,
+    canary: Arc<ArcSwapOption<Canary>>,
 }
 
 pub struct AppRouterInner {
+    /// the project name, also a host alias of the project when name aliases are on
+    pub name: String,
     pub code: String,
+    /// hash of the code and config, identifying the version of the project
+    pub hash: String,
+    pub deployed_at: SystemTime,
+    pub stats: VersionStats,
     pub router: Router<MethodRoute>,
+    pub cookie_key: Option<Key>,
+    pub extensions: Vec<String>,
+    /// every middleware referenced by the project, to validate it against the server
+    pub middlewares: Vec<MiddlewareConfig>,
+    pub public: Option<Arc<PublicDir>>,
+    pub redirects: Redirects,
+    /// the project's cors policy, for responses of paths and methods without a route
+    pub cors: Option<CorsConfig>,
 }
 
 impl SwappableAppRouter {
-    pub fn try_new(code: impl Into<String>, routes: ProjectRoutes) -> anyhow::Result<Self> {
-        let router = Self::get_router(routes)?;
-        let inner = AppRouterInner::new(code, router);
+    /// a router for a project without extensions, see `try_new_with`
+    pub fn try_new(code: impl Into<String>, config: ProjectConfig) -> anyhow::Result<Self> {
+        Self::try_new_with(code, config, &Extensions::default())
+    }
+
+    /// a router for a project whose bundle is checked with the extensions it lists,
+    /// which must be among the given ones
+    pub fn try_new_with(
+        code: impl Into<String>,
+        config: ProjectConfig,
+        extensions: &Extensions,
+    ) -> anyhow::Result<Self> {
+        let inner = Self::get_inner(code, config, extensions)?;
         Ok(Self {
             inner: Arc::new(ArcSwap::from_pointee(inner)),
+            history: Default::default(),
+            canary: Default::default(),
         })
     }
 
-    pub fn swap(&self, code: impl Into<String>, routes: ProjectRoutes) -> anyhow::Result<()> {
-        let router = Self::get_router(routes)?;
-        let inner = AppRouterInner::new(code, router);
-        self.inner.store(Arc::new(inner));
+    /// serve a new version, the current one is kept in the history
+    pub fn swap(&self, code: impl Into<String>, config: ProjectConfig) -> anyhow::Result<()> {
+        self.swap_with(code, config, &Extensions::default())
+    }
+
+    /// serve a new version checked with the given extensions, see `try_new_with`
+    pub fn swap_with(
+        &self,
+        code: impl Into<String>,
+        config: ProjectConfig,
+        extensions: &Extensions,
+    ) -> anyhow::Result<()> {
+        let inner = Self::get_inner(code, config, extensions)?;
+        self.store(Arc::new(inner));
+        Ok(())
+    }
+
+    /// serve the code and config of another router from this one
+    pub fn replace(&self, other: &SwappableAppRouter) {
+        self.store(other.inner.load_full());
+    }
+
+    /// serve a previous version again, without rebuilding it
+    pub fn rollback(&self, hash: &str) -> anyhow::Result<()> {
+        if self.load().hash == hash {
+            return Ok(());
+        }
+        let history = self.history.lock().unwrap();
+        let version = history.iter().find(|v| v.hash == hash).cloned();
+        drop(history);
+        match version {
+            Some(version) => {
+                self.store(version);
+                Ok(())
+            }
+            None => bail!("version {hash} of {} not found", self.load().name),
+        }
+    }
+
+    /// the current version followed by the previous ones, newest first
+    pub fn versions(&self) -> Vec<AppRouter> {
+        let history = self.history.lock().unwrap();
+        std::iter::once(self.inner.load_full())
+            .chain(history.iter().cloned())
+            .map(AppRouter)
+            .collect()
+    }
+
+    /// serve the version of another router to a percentage of the requests next to
+    /// the current one, whose stats restart to be compared with the canary's
+    pub fn set_canary(&self, other: &SwappableAppRouter, weight: u8) -> anyhow::Result<()> {
+        if weight > 100 {
+            bail!("canary weight {weight} is not a percentage");
+        }
+        self.load().stats.reset();
+        let router = other.load();
+        self.canary.store(Some(Arc::new(Canary { router, weight })));
         Ok(())
     }
 
+    pub fn canary(&self) -> Option<Arc<Canary>> {
+        self.canary.load_full()
+    }
+
+    /// serve the canary to all requests, the current version is kept in the history
+    pub fn promote_canary(&self) -> anyhow::Result<()> {
+        match self.canary.swap(None) {
+            Some(canary) => {
+                self.store(canary.router.0.clone());
+                Ok(())
+            }
+            None => bail!("{} has no canary", self.load().name),
+        }
+    }
+
+    /// stop serving the canary, false if there is none
+    pub fn abort_canary(&self) -> bool {
+        self.canary.swap(None).is_some()
+    }
+
+    /// the version serving a request, and whether to pin the client to it with a cookie
+    pub fn pick(&self, headers: &HeaderMap) -> (AppRouter, bool) {
+        canary::select(self.load(), self.canary.load().as_deref(), headers)
+    }
+
+    fn store(&self, inner: Arc<AppRouterInner>) {
+        let mut history = self.history.lock().unwrap();
+        let previous = self.inner.swap(inner.clone());
+        history.retain(|v| v.hash != previous.hash && v.hash != inner.hash);
+        if previous.hash != inner.hash {
+            history.push_front(previous);
+        }
+        history.truncate(HISTORY_LIMIT);
+    }
+
+    fn get_inner(
+        code: impl Into<String>,
+        config: ProjectConfig,
+        extensions: &Extensions,
+    ) -> anyhow::Result<AppRouterInner> {
+        let code = code.into();
+        let public = config
+            .public
+            .clone()
+            .map(PublicDir::load)
+            .transpose()?
+            .map(Arc::new);
+        // the extensions of the workers, see `run_route`
+        let mut resolved = extensions.resolve(&config.extensions)?;
+        if let Some(public) = &public {
+            resolved.push(public.clone());
+        }
+        validate_handlers(&code, &config, &resolved)?;
+        let mut middlewares: Vec<MiddlewareConfig> = Vec::new();
+        let groups = config.groups.iter().flat_map(|g| &g.middlewares);
+        let routes = config
+            .routes
+            .values()
+            .flatten()
+            .flat_map(|r| &r.middlewares);
+        for m in config.middlewares.iter().chain(groups).chain(routes) {
+            if !middlewares.contains(m) {
+                middlewares.push(m.clone());
+            }
+        }
+
+        let router = Self::get_router(&config)?;
+        let hash = version_hash(&code, &config);
+        let mut inner = AppRouterInner::new(code, router);
+        inner.hash = hash;
+        inner.name = config.name;
+        inner.cookie_key = cookie_key(config.secret.as_deref())?;
+        inner.extensions = config.extensions;
+        inner.middlewares = middlewares;
+        inner.redirects = Redirects::try_new(&config.redirects, &config.rewrites)?;
+        inner.cors = config.cors;
+        inner.public = public;
+        Ok(inner)
+    }
+
     pub fn load(&self) -> AppRouter {
         AppRouter(self.inner.load_full())
     }
 
-    fn get_router(routes: ProjectRoutes) -> anyhow::Result<Router<MethodRoute>> {
+    fn get_router(config: &ProjectConfig) -> anyhow::Result<Router<MethodRoute>> {
         let mut router = Router::new();
-        for (path, methods) in routes {
+        for (path, methods) in &config.routes {
+            let mut shared = config.middlewares.clone();
+            for group in config.groups.iter().filter(|g| g.contains(path)) {
+                shared.extend(group.middlewares.iter().cloned());
+            }
+
             let mut method_route = MethodRoute::default();
             for method in methods {
-                match method.method {
-                    Method::GET => method_route.get = Some(method.handler),
-                    Method::HEAD => method_route.head = Some(method.handler),
-                    Method::DELETE => method_route.delete = Some(method.handler),
-                    Method::OPTIONS => method_route.options = Some(method.handler),
-                    Method::PATCH => method_route.patch = Some(method.handler),
-                    Method::POST => method_route.post = Some(method.handler),
-                    Method::PUT => method_route.put = Some(method.handler),
-                    Method::TRACE => method_route.trace = Some(method.handler),
-                    Method::CONNECT => method_route.connect = Some(method.handler),
-                    v => unreachable!("unsupported method {v}"),
+                let mut middlewares = shared.clone();
+                middlewares.extend(method.middlewares.iter().cloned());
+                let handler = RouteHandler {
+                    handler: method.handler.clone(),
+                    middlewares,
+                    cors: method.cors.clone().or_else(|| config.cors.clone()),
+                    timeout: method.timeout.or(config.timeout),
+                    max_body_size: method.max_body_size.or(config.max_body_size),
+                };
+                if !method_route.insert(&method.method, handler) {
+                    bail!("method {} is defined twice for route {path}", method.method);
                 }
             }
-            router.insert(path, method_route)?;
+            router
+                .insert(path, method_route)
+                .with_context(|| format!("invalid route {path}"))?;
         }
         Ok(router)
     }
 }
 
+/// the code and the canonical yaml of the config, stable across server versions
+fn version_hash(code: &str, config: &ProjectConfig) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(code.as_bytes());
+    hasher.update(config.source.as_bytes());
+    hasher.finalize().to_string()[..16].to_string()
+}
+
+/// check that every handler and js middleware of the config is a function exported by the
+/// bundle, which must evaluate with the extensions of its workers
+pub fn validate_handlers(
+    code: &str,
+    config: &ProjectConfig,
+    extensions: &[Arc<dyn Extension>],
+) -> anyhow::Result<()> {
+    let exports =
+        exports(code, extensions).context("failed to evaluate the bundle to check its handlers")?;
+    check_handlers(&exports, config)
+}
+
+fn check_handlers(exports: &HashMap<String, bool>, config: &ProjectConfig) -> anyhow::Result<()> {
+    let mut errors = Vec::new();
+    let mut check = |name: &str, location: String| {
+        if let Some(e) = check_export(exports, name) {
+            errors.push(format!("{location}: {e}"));
+        }
+    };
+    for m in &config.middlewares {
+        if let Some(name) = m.js_name() {
+            check(name, "project middleware".to_string());
+        }
+    }
+    for group in &config.groups {
+        for name in group
+            .middlewares
+            .iter()
+            .filter_map(MiddlewareConfig::js_name)
+        {
+            check(name, format!("group {} middleware", group.prefix));
+        }
+    }
+    for (path, routes) in &config.routes {
+        for route in routes {
+            check(&route.handler, format!("{} {path} handler", route.method));
+            for name in route
+                .middlewares
+                .iter()
+                .filter_map(MiddlewareConfig::js_name)
+            {
+                check(name, format!("{} {path} middleware", route.method));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort();
+    bail!("invalid handlers in config:\n{}", errors.join("\n"))
+}
+
+fn check_export(exports: &HashMap<String, bool>, name: &str) -> Option<String> {
+    match exports.get(name) {
+        Some(true) => None,
+        Some(false) => Some(format!("{name} is exported but is not a function")),
+        None => {
+            let functions = exports.iter().filter(|(_, f)| **f).map(|(k, _)| k.as_str());
+            match closest(name, functions) {
+                Some(hint) => Some(format!("{name} is not exported, did you mean {hint}?")),
+                None => Some(format!("{name} is not exported")),
+            }
+        }
+    }
+}
+
+/// the candidate with the smallest edit distance to a name, if it is close enough to be a typo
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max = (name.chars().count() / 3).max(2);
+    candidates
+        .map(|c| (levenshtein(name, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min()
+        .map(|(_, c)| c)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
 #[derive(Clone)]
 pub struct AppRouter(Arc<AppRouterInner>);
 
@@ -81,31 +462,34 @@ impl AppRouter {
         &'m self,
         method: Method,
         path: &'p str,
-    ) -> Result<Match<&str>, AppError>
+    ) -> Result<Match<'m, 'm, &'m RouteHandler>, AppError>
     where
         'p: 'm,
     {
         let Ok(ret) = self.router.at(path) else {
             return Err(AppError::RoutePathNotFound(path.to_string()));
         };
-        let s = match method {
-            Method::GET => ret.value.get.as_deref(),
-            Method::HEAD => ret.value.head.as_deref(),
-            Method::DELETE => ret.value.delete.as_deref(),
-            Method::OPTIONS => ret.value.options.as_deref(),
-            Method::PATCH => ret.value.patch.as_deref(),
-            Method::POST => ret.value.post.as_deref(),
-            Method::PUT => ret.value.put.as_deref(),
-            Method::TRACE => ret.value.trace.as_deref(),
-            Method::CONNECT => ret.value.connect.as_deref(),
-            _ => unreachable!(),
-        }
-        .ok_or(AppError::RouteMethodNotAllowed(method))?;
+        let Some(s) = ret.value.get(&method) else {
+            return Err(AppError::RouteMethodNotAllowed(method, ret.value.allow()));
+        };
         Ok(Match {
             value: s,
             params: ret.params,
         })
     }
+
+    /// answer OPTIONS for a route without its own OPTIONS handler
+    pub fn options(&self, path: &str) -> Option<Response> {
+        let route = self.router.at(path).ok()?.value;
+        if route.get(&Method::OPTIONS).is_some() {
+            return None;
+        }
+        Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(ALLOW, route.allow())
+            .body(Body::empty())
+            .ok()
+    }
 }
 
 impl Deref for AppRouter {
@@ -116,15 +500,56 @@ impl Deref for AppRouter {
     }
 }
 
+impl RouteHandler {
+    pub fn new(handler: impl Into<String>) -> Self {
+        Self {
+            handler: handler.into(),
+            middlewares: Vec::new(),
+            cors: None,
+            timeout: None,
+            max_body_size: None,
+        }
+    }
+}
+
 impl AppRouterInner {
     pub fn new(code: impl Into<String>, router: Router<MethodRoute>) -> Self {
         Self {
+            name: String::new(),
             code: code.into(),
+            hash: String::new(),
+            deployed_at: SystemTime::now(),
+            stats: VersionStats::default(),
             router,
+            cookie_key: None,
+            extensions: Vec::new(),
+            middlewares: Vec::new(),
+            public: None,
+            redirects: Redirects::default(),
+            cors: None,
         }
     }
 }
 
+/// a bundle exporting an empty function for every handler and js middleware of the config
+#[cfg(test)]
+pub(crate) fn stub_bundle(config: &ProjectConfig) -> String {
+    let groups = config.groups.iter().flat_map(|g| &g.middlewares);
+    let routes = config.routes.values().flatten();
+    let middlewares = config
+        .middlewares
+        .iter()
+        .chain(groups)
+        .chain(routes.clone().flat_map(|r| &r.middlewares))
+        .filter_map(MiddlewareConfig::js_name);
+    let exports: Vec<_> = routes
+        .map(|r| r.handler.as_str())
+        .chain(middlewares)
+        .map(|name| format!("{name:?}: function() {{}}"))
+        .collect();
+    format!("(function(){{ return {{ {} }}; }})();", exports.join(", "))
+}
+
 #[cfg(test)]
 mod tests {
     use matchit::Router;
@@ -149,8 +574,8 @@ mod tests {
     fn test_method_route() -> anyhow::Result<()> {
         let mut router = Router::new();
         let method_route = MethodRoute {
-            get: Some("get".to_string()),
-            post: Some("post".to_string()),
+            get: Some(RouteHandler::new("get")),
+            post: Some(RouteHandler::new("post")),
             ..Default::default()
         };
         router.insert("/aaa", method_route.clone())?;
@@ -165,7 +590,7 @@ mod tests {
     fn test_app_route() -> anyhow::Result<()> {
         let mut router = Router::new();
         let method_route = MethodRoute {
-            get: Some("get".to_string()),
+            get: Some(RouteHandler::new("get")),
             ..Default::default()
         };
         router.insert("/bbb/{*id}", method_route)?;
@@ -173,7 +598,7 @@ mod tests {
         let inner = AppRouterInner::new("", router);
         let app_router = AppRouter(Arc::new(inner));
         let res = app_router.match_it(Method::GET, "/bbb/123")?;
-        assert_eq!(res.value, "get");
+        assert_eq!(res.value.handler, "get");
         assert_eq!((res.params.get("id")), Some("123"));
         Ok(())
     }
@@ -190,10 +615,10 @@ mod tests {
         "#,
         )?;
 
-        let router = SwappableAppRouter::try_new("", config.routes)?;
+        let router = SwappableAppRouter::try_new(stub_bundle(&config), config)?;
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/123")?;
-        assert_eq!(m.value, "hello1");
+        assert_eq!(m.value.handler, "hello1");
 
         let newconfig: ProjectConfig = serde_yaml::from_str(
             r#"
@@ -207,12 +632,292 @@ mod tests {
               handler: handler2
         "#,
         )?;
-        router.swap("", newconfig.routes)?;
+        router.swap(stub_bundle(&newconfig), newconfig)?;
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/123")?;
-        assert_eq!(m.value, "hello1");
+        assert_eq!(m.value.handler, "hello1");
         let m = app_router.match_it(Method::POST, "/api/goodbye/123")?;
-        assert_eq!(m.value, "handler2");
+        assert_eq!(m.value.handler, "handler2");
+        Ok(())
+    }
+
+    #[test]
+    fn version_hash_should_ignore_formatting() -> anyhow::Result<()> {
+        let flow = "{ name: dino-test, routes: { /api: [{ method: GET, handler: hello }] } }";
+        let block = r#"
+# the same config as a block
+name: dino-test
+routes:
+  /api:
+    - method: GET
+      handler: hello
+"#;
+        let hash = |yaml: &str| version_hash("code", &ProjectConfig::parse(yaml).unwrap());
+        assert_eq!(hash(flow), hash(block));
+        assert_ne!(hash(flow), hash(&flow.replace("hello", "world")));
+        assert_ne!(
+            hash(flow),
+            version_hash("other", &ProjectConfig::parse(flow)?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn app_router_rollback_should_work() -> anyhow::Result<()> {
+        let config = |handler: &str| -> ProjectConfig {
+            let yaml = format!("{{ name: dino-test, routes: {{ /api: [{{ method: GET, handler: {handler} }}] }} }}");
+            serde_yaml::from_str(&yaml).unwrap()
+        };
+        let router = SwappableAppRouter::try_new(stub_bundle(&config("v1")), config("v1"))?;
+        let v1 = router.load().hash.clone();
+        router.swap(stub_bundle(&config("v2")), config("v2"))?;
+        let v2 = router.load().hash.clone();
+        assert_ne!(v1, v2);
+
+        router.rollback(&v1)?;
+        assert_eq!(
+            router.load().match_it(Method::GET, "/api")?.value.handler,
+            "v1"
+        );
+        let versions: Vec<_> = router.versions().iter().map(|v| v.hash.clone()).collect();
+        assert_eq!(versions, vec![v1.clone(), v2.clone()]);
+
+        // the same code and config is the same version
+        router.swap(stub_bundle(&config("v1")), config("v1"))?;
+        assert_eq!(router.versions().len(), 2);
+        assert!(router.rollback("unknown").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn route_middlewares_should_be_chained() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+        name: dino-test
+        middlewares: [request-id]
+        groups:
+          - prefix: /api/admin
+            middlewares: [{ before: auth }]
+        routes:
+          /api/admin/users:
+            - method: GET
+              handler: users
+              middlewares: [{ after: audit }]
+          /api/hello:
+            - method: GET
+              handler: hello
+        "#,
+        )?;
+
+        let router = SwappableAppRouter::try_new(stub_bundle(&config), config)?.load();
+        let m = router.match_it(Method::GET, "/api/admin/users")?;
+        assert_eq!(
+            m.value.middlewares,
+            vec![
+                MiddlewareConfig::Named("request-id".to_string()),
+                MiddlewareConfig::Before {
+                    before: "auth".to_string()
+                },
+                MiddlewareConfig::After {
+                    after: "audit".to_string()
+                },
+            ]
+        );
+        let m = router.match_it(Method::GET, "/api/hello")?;
+        assert_eq!(
+            m.value.middlewares,
+            vec![MiddlewareConfig::Named("request-id".to_string())]
+        );
+        assert_eq!(router.middlewares.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn route_cors_should_override_project() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+        name: dino-test
+        cors:
+          origins: [https://example.com]
+        routes:
+          /api/public:
+            - method: GET
+              handler: public
+              cors:
+                origins: ["*"]
+          /api/hello:
+            - method: GET
+              handler: hello
+        "#,
+        )?;
+
+        let router = SwappableAppRouter::try_new(stub_bundle(&config), config)?.load();
+        let m = router.match_it(Method::GET, "/api/public")?;
+        assert_eq!(m.value.cors.as_ref().unwrap().origins, ["*"]);
+        let m = router.match_it(Method::GET, "/api/hello")?;
+        assert_eq!(
+            m.value.cors.as_ref().unwrap().origins,
+            ["https://example.com"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn any_and_extension_methods_should_match() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+        name: dino-test
+        routes:
+          /dav/{*path}:
+            - method: PROPFIND
+              handler: propfind
+            - method: GET
+              handler: get
+          /api/echo:
+            - method: ANY
+              handler: echo
+            - method: POST
+              handler: post
+        "#,
+        )?;
+
+        let router = SwappableAppRouter::try_new(stub_bundle(&config), config)?.load();
+        let propfind = Method::from_bytes(b"PROPFIND")?;
+        let m = router.match_it(propfind.clone(), "/dav/a/b")?;
+        assert_eq!(m.value.handler, "propfind");
+        assert!(router.match_it(Method::PUT, "/dav/a").is_err());
+        assert!(router
+            .match_it(Method::from_bytes(b"MKCOL")?, "/dav/a")
+            .is_err());
+
+        let m = router.match_it(Method::POST, "/api/echo")?;
+        assert_eq!(m.value.handler, "post");
+        let m = router.match_it(Method::DELETE, "/api/echo")?;
+        assert_eq!(m.value.handler, "echo");
+        let m = router.match_it(propfind, "/api/echo")?;
+        assert_eq!(m.value.handler, "echo");
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_methods_should_fail() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+        name: dino-test
+        routes:
+          /api/hello:
+            - method: GET
+              handler: hello1
+            - method: get
+              handler: hello2
+        "#,
+        )?;
+        assert!(SwappableAppRouter::try_new(stub_bundle(&config), config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn head_and_options_should_be_automatic() -> anyhow::Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+        name: dino-test
+        routes:
+          /api/hello:
+            - method: GET
+              handler: hello
+            - method: POST
+              handler: create
+          /api/custom:
+            - method: OPTIONS
+              handler: options
+        "#,
+        )?;
+
+        let router = SwappableAppRouter::try_new(stub_bundle(&config), config)?.load();
+        let m = router.match_it(Method::HEAD, "/api/hello")?;
+        assert_eq!(m.value.handler, "hello");
+
+        let res = router.options("/api/hello").unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()[ALLOW], "GET, HEAD, POST, OPTIONS");
+        assert!(router.options("/api/custom").is_none());
+        assert!(router.options("/api/missing").is_none());
+
+        let Err(AppError::RouteMethodNotAllowed(_, allow)) =
+            router.match_it(Method::DELETE, "/api/hello")
+        else {
+            panic!("expected method not allowed");
+        };
+        assert_eq!(allow, "GET, HEAD, POST, OPTIONS");
+        Ok(())
+    }
+
+    #[test]
+    fn missing_handlers_should_fail() -> anyhow::Result<()> {
+        let code = "(function(){ function hello(){} function auth(){} return { hello, auth, version: 1 }; })();";
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+        name: dino-test
+        middlewares: [{ before: auht }]
+        routes:
+          /api/hello:
+            - method: GET
+              handler: helo
+            - method: POST
+              handler: version
+            - method: PUT
+              handler: something
+        "#,
+        )?;
+        let err = SwappableAppRouter::try_new(code, config)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("GET /api/hello handler: helo is not exported, did you mean hello?"));
+        assert!(err.contains("POST /api/hello handler: version is exported but is not a function"));
+        assert!(err.contains("PUT /api/hello handler: something is not exported\n"));
+        assert!(err.contains("project middleware: auht is not exported, did you mean auth?"));
+        Ok(())
+    }
+
+    #[test]
+    fn unevaluable_bundle_should_fail_validation() -> anyhow::Result<()> {
+        let code = "(function(){ greet(); function hello(){} return { hello }; })();";
+        let config = ProjectConfig::parse(
+            "{ name: dino-test, routes: { /api: [{ method: GET, handler: hello }] } }",
+        )?;
+        let err = validate_handlers(code, &config, &[]).unwrap_err();
+        assert!(err.to_string().contains("failed to evaluate the bundle"));
+        assert!(SwappableAppRouter::try_new(code, config).is_err());
+        Ok(())
+    }
+
+    struct Greeter;
+
+    impl Extension for Greeter {
+        fn name(&self) -> &str {
+            "greeter"
+        }
+
+        fn register<'js>(&self, ctx: &rquickjs::Ctx<'js>) -> rquickjs::Result<()> {
+            let greet = rquickjs::Function::new(ctx.clone(), |name: String| format!("hi {name}"))?;
+            ctx.globals().set("greet", greet)
+        }
+    }
+
+    #[test]
+    fn bundle_should_be_validated_with_its_extensions() -> anyhow::Result<()> {
+        let code = "(function(){ greet('dino'); function hello(){} return { hello }; })();";
+        let config = || {
+            ProjectConfig::parse(
+                "{ name: dino-test, extensions: [greeter], routes: { /api: [{ method: GET, handler: hello }] } }",
+            )
+        };
+        let extensions = Extensions::new([Arc::new(Greeter) as Arc<dyn Extension>]);
+        let router = SwappableAppRouter::try_new_with(code, config()?, &extensions)?;
+        router.swap_with(code, config()?, &extensions)?;
+        // not registered on the server
+        assert!(router.swap(code, config()?).is_err());
         Ok(())
     }
 }
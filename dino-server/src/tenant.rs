@@ -0,0 +1,393 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::bail;
+use arc_swap::ArcSwap;
+
+use crate::router::SwappableAppRouter;
+
+/// A project served by the server under one or more hosts, optionally below a path prefix.
+#[derive(Clone)]
+pub struct TenentRouter {
+    hosts: Vec<String>,
+    prefix: String,
+    router: SwappableAppRouter,
+}
+
+/// The tenants of a server, looked up by the host and path of a request.
+#[derive(Clone, Default)]
+pub struct Tenants {
+    exact: HashMap<String, Vec<Mount>>,
+    /// `*.example.test` patterns stored as `.example.test`, longest first
+    wildcards: Vec<(String, Vec<Mount>)>,
+    fallback: Option<SwappableAppRouter>,
+    /// the tenants as given, to derive a changed set from
+    tenants: Vec<TenentRouter>,
+    /// serve projects under `<name>` and `<name>.localhost` as well, for local development
+    name_aliases: bool,
+}
+
+/// The tenants of a running server, replaced as a whole when projects are added or removed.
+#[derive(Clone, Default)]
+pub struct SwappableTenants {
+    inner: Arc<ArcSwap<Tenants>>,
+}
+
+/// a project below a path prefix of a host, the prefix is empty for the whole host
+#[derive(Clone)]
+struct Mount {
+    prefix: String,
+    router: SwappableAppRouter,
+}
+
+impl TenentRouter {
+    /// `host` may be a wildcard subdomain pattern like `*.example.test`
+    pub fn new(host: impl Into<String>, router: SwappableAppRouter) -> Self {
+        Self {
+            hosts: vec![host.into()],
+            prefix: String::new(),
+            router,
+        }
+    }
+
+    /// mount the project below a path prefix like `/tenants/billing`, which is stripped
+    /// before routing and passed to handlers as `req.basePath`
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// serve the project under another host as well
+    pub fn alias(mut self, host: impl Into<String>) -> Self {
+        self.hosts.push(host.into());
+        self
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    pub fn path_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn router(&self) -> &SwappableAppRouter {
+        &self.router
+    }
+}
+
+impl Tenants {
+    /// index the tenants by host
+    pub fn try_new(
+        tenants: Vec<TenentRouter>,
+        fallback: Option<SwappableAppRouter>,
+    ) -> anyhow::Result<Self> {
+        let mut ret = Self {
+            fallback,
+            ..Default::default()
+        };
+        for tenant in &tenants {
+            if !tenant.prefix.is_empty() && !tenant.prefix.starts_with('/') {
+                bail!("invalid prefix {}, expected /<path>", tenant.prefix);
+            }
+            for host in &tenant.hosts {
+                let host = normalize(host);
+                let mounts = if let Some(suffix) = host.strip_prefix('*') {
+                    if !suffix.starts_with('.') || suffix.contains('*') {
+                        bail!("invalid wildcard host {host}, expected *.<domain>");
+                    }
+                    let i = match ret.wildcards.iter().position(|(s, _)| s == suffix) {
+                        Some(i) => i,
+                        None => {
+                            ret.wildcards.push((suffix.to_string(), Vec::new()));
+                            ret.wildcards.len() - 1
+                        }
+                    };
+                    &mut ret.wildcards[i].1
+                } else {
+                    if host.contains('*') {
+                        bail!("invalid wildcard host {host}, expected *.<domain>");
+                    }
+                    ret.exact.entry(host.clone()).or_default()
+                };
+                if mounts.iter().any(|m| m.prefix == tenant.prefix) {
+                    bail!(
+                        "host {host}{} is used by more than one tenant",
+                        tenant.prefix
+                    );
+                }
+                mounts.push(Mount {
+                    prefix: tenant.prefix.clone(),
+                    router: tenant.router.clone(),
+                });
+            }
+        }
+        let mounts = ret
+            .exact
+            .values_mut()
+            .chain(ret.wildcards.iter_mut().map(|(_, m)| m));
+        for mounts in mounts {
+            mounts.sort_by_key(|m| std::cmp::Reverse(m.prefix.len()));
+        }
+        ret.wildcards
+            .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        ret.tenants = tenants;
+        Ok(ret)
+    }
+
+    /// also serve every project under `<name>` and `<name>.localhost` by its current name,
+    /// for hosts no tenant claims
+    pub fn name_aliases(mut self, enabled: bool) -> Self {
+        self.name_aliases = enabled;
+        self
+    }
+
+    /// the same setup with another set of tenants
+    pub fn with_tenants(&self, tenants: Vec<TenentRouter>) -> anyhow::Result<Self> {
+        Ok(Self::try_new(tenants, self.fallback.clone())?.name_aliases(self.name_aliases))
+    }
+
+    /// the tenant of a `Host` header value and request path along with its path prefix:
+    /// exact hosts first, then the most specific wildcard, then the name aliases, then
+    /// the fallback tenant
+    pub fn get(&self, host: &str, path: &str) -> Option<(&SwappableAppRouter, &str)> {
+        let host = normalize(strip_port(host));
+        let wildcards = self
+            .wildcards
+            .iter()
+            .filter(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()))
+            .map(|(_, mounts)| mounts);
+        self.exact
+            .get(&host)
+            .into_iter()
+            .chain(wildcards)
+            .flatten()
+            .find(|m| under(path, &m.prefix))
+            .map(|m| (&m.router, m.prefix.as_str()))
+            .or_else(|| self.alias(&host).map(|r| (r, "")))
+            .or(self.fallback.as_ref().map(|r| (r, "")))
+    }
+
+    /// looked up on every request so a renamed project drops its old name
+    fn alias(&self, host: &str) -> Option<&SwappableAppRouter> {
+        if !self.name_aliases {
+            return None;
+        }
+        let name = host.strip_suffix(".localhost").unwrap_or(host);
+        self.tenants
+            .iter()
+            .map(|t| &t.router)
+            .find(|r| normalize(&r.load().name) == name)
+    }
+
+    pub fn tenants(&self) -> &[TenentRouter] {
+        &self.tenants
+    }
+
+    pub fn fallback(&self) -> Option<&SwappableAppRouter> {
+        self.fallback.as_ref()
+    }
+
+    pub fn routers(&self) -> impl Iterator<Item = &SwappableAppRouter> {
+        self.exact
+            .values()
+            .chain(self.wildcards.iter().map(|(_, m)| m))
+            .flatten()
+            .map(|m| &m.router)
+            .chain(self.fallback.iter())
+    }
+}
+
+impl SwappableTenants {
+    pub fn new(tenants: Tenants) -> Self {
+        Self {
+            inner: Arc::new(ArcSwap::from_pointee(tenants)),
+        }
+    }
+
+    pub fn swap(&self, tenants: Tenants) {
+        self.inner.store(Arc::new(tenants));
+    }
+
+    pub fn load(&self) -> Arc<Tenants> {
+        self.inner.load_full()
+    }
+}
+
+/// whether a path is below a prefix, matching whole segments only
+fn under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// `example.test:3000` becomes `example.test`, ipv6 hosts keep their brackets
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return match host.find(']') {
+            Some(i) => &host[..=i],
+            None => host,
+        };
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ProjectConfig, router::stub_bundle};
+
+    fn router(name: &str) -> SwappableAppRouter {
+        let config: ProjectConfig =
+            serde_yaml::from_str(&format!("{{ name: {name}, routes: {{}} }}")).unwrap();
+        SwappableAppRouter::try_new(stub_bundle(&config), config).unwrap()
+    }
+
+    fn name_of(tenant: Option<(&SwappableAppRouter, &str)>) -> Option<String> {
+        tenant.map(|(r, _)| r.load().name.clone())
+    }
+
+    #[test]
+    fn tenants_should_match_hosts() -> anyhow::Result<()> {
+        let tenants = Tenants::try_new(
+            vec![
+                TenentRouter::new("example.test", router("site")).alias("www.example.test"),
+                TenentRouter::new("*.example.test", router("apps")),
+                TenentRouter::new("*.api.example.test", router("api")),
+            ],
+            None,
+        )?;
+        let get = |host: &str| name_of(tenants.get(host, "/"));
+        assert_eq!(get("example.test:3000").as_deref(), Some("site"));
+        assert_eq!(get("WWW.example.test.").as_deref(), Some("site"));
+        assert_eq!(get("blog.example.test").as_deref(), Some("apps"));
+        assert_eq!(get("v1.api.example.test").as_deref(), Some("api"));
+        assert_eq!(get("api.localhost"), None);
+        assert_eq!(get("other.test"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn name_aliases_should_be_opt_in() -> anyhow::Result<()> {
+        let renamed = router("api");
+        let tenants = Tenants::try_new(
+            vec![
+                TenentRouter::new("*.example.test", router("apps")),
+                TenentRouter::new("api.test", renamed.clone()),
+                TenentRouter::new("web.test", router("web.example.test")),
+            ],
+            None,
+        )?
+        .name_aliases(true);
+        let get = |host: &str| name_of(tenants.get(host, "/"));
+        assert_eq!(get("api.localhost").as_deref(), Some("api"));
+        assert_eq!(get("api").as_deref(), Some("api"));
+        // an alias never takes a host from a configured tenant
+        assert_eq!(get("web.example.test").as_deref(), Some("apps"));
+
+        let config = ProjectConfig::parse("{ name: search, routes: {} }")?;
+        renamed.swap(stub_bundle(&config), config)?;
+        assert_eq!(get("api.localhost"), None);
+        assert_eq!(get("search.localhost").as_deref(), Some("search"));
+
+        let tenants = tenants.with_tenants(tenants.tenants().to_vec())?;
+        assert!(tenants.get("search", "/").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn fallback_tenant_should_be_used() -> anyhow::Result<()> {
+        let tenants = Tenants::try_new(
+            vec![TenentRouter::new("localhost", router("dev"))],
+            Some(router("dev")),
+        )?;
+        assert_eq!(
+            name_of(tenants.get("192.168.1.20:3000", "/")).as_deref(),
+            Some("dev")
+        );
+        assert_eq!(
+            name_of(tenants.get("[::1]:3000", "/")).as_deref(),
+            Some("dev")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tenants_should_match_path_prefixes() -> anyhow::Result<()> {
+        let tenants = Tenants::try_new(
+            vec![
+                TenentRouter::new("example.test", router("billing")).prefix("/tenants/billing/"),
+                TenentRouter::new("example.test", router("search")).prefix("/tenants/search"),
+                TenentRouter::new("example.test", router("site")),
+            ],
+            None,
+        )?;
+        let get = |path: &str| {
+            tenants
+                .get("example.test", path)
+                .map(|(r, base)| (r.load().name.clone(), base.to_string()))
+        };
+        assert_eq!(
+            get("/tenants/billing/api/invoices"),
+            Some(("billing".into(), "/tenants/billing".into()))
+        );
+        assert_eq!(
+            get("/tenants/search"),
+            Some(("search".into(), "/tenants/search".into()))
+        );
+        assert_eq!(get("/tenants/searching"), Some(("site".into(), "".into())));
+
+        let duplicate = vec![
+            TenentRouter::new("example.test", router("a")).prefix("/a"),
+            TenentRouter::new("example.test", router("b")).prefix("/a/"),
+        ];
+        assert!(Tenants::try_new(duplicate, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn swappable_tenants_should_work() -> anyhow::Result<()> {
+        let tenants = SwappableTenants::new(Tenants::try_new(
+            vec![TenentRouter::new("a.test", router("a"))],
+            None,
+        )?);
+        let running = tenants.clone();
+        assert_eq!(
+            name_of(running.load().get("a.test", "/")).as_deref(),
+            Some("a")
+        );
+
+        tenants.swap(Tenants::try_new(
+            vec![TenentRouter::new("b.test", router("b"))],
+            None,
+        )?);
+        assert!(running.load().get("a.test", "/").is_none());
+        assert_eq!(
+            name_of(running.load().get("b.test", "/")).as_deref(),
+            Some("b")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_tenants_should_fail() {
+        let duplicate = vec![
+            TenentRouter::new("example.test", router("a")),
+            TenentRouter::new("example.test", router("b")),
+        ];
+        assert!(Tenants::try_new(duplicate, None).is_err());
+        let wildcard = vec![TenentRouter::new("api*.example.test", router("a"))];
+        assert!(Tenants::try_new(wildcard, None).is_err());
+    }
+
+    #[test]
+    fn strip_port_should_work() {
+        assert_eq!(strip_port("localhost:3000"), "localhost");
+        assert_eq!(strip_port("localhost"), "localhost");
+        assert_eq!(strip_port("[::1]:3000"), "[::1]");
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context as _};
+use arc_swap::ArcSwap;
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::{default_provider, sign::any_supported_type},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::{info, warn};
+
+/// how often the certificate files are checked for changes
+const CERT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+/// time a client has to complete the handshake before its connection is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An https listener, with certificates picked by the host name the client asks for.
+#[derive(Clone)]
+pub struct TlsListener {
+    port: u16,
+    /// shared with the running listener, see `replace`
+    files: Arc<ArcSwap<Vec<CertFiles>>>,
+    handshake_timeout: Duration,
+}
+
+/// pem files of a certificate chain and its private key, for some hosts or the default one
+#[derive(Debug, Clone, PartialEq)]
+struct CertFiles {
+    hosts: Vec<String>,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+/// Resolves the certificate of a connection by its sni host name, the certificates are
+/// replaced as a whole when their files or the list of files change.
+#[derive(Debug)]
+pub(crate) struct CertResolver {
+    files: Arc<ArcSwap<Vec<CertFiles>>>,
+    certs: ArcSwap<Certs>,
+}
+
+/// a listener bound to its port, ready to serve
+pub(crate) struct BoundTls {
+    listener: TcpListener,
+    resolver: Arc<CertResolver>,
+    handshake_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Certs {
+    exact: HashMap<String, Arc<CertifiedKey>>,
+    /// `*.example.test` hosts stored as `.example.test`, longest first
+    wildcards: Vec<(String, Arc<CertifiedKey>)>,
+    default: Option<Arc<CertifiedKey>>,
+    /// the files the certificates were loaded from and their modification times
+    files: Arc<Vec<CertFiles>>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl TlsListener {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            files: Default::default(),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    /// the certificate for clients asking for a host without a certificate of its own
+    pub fn cert(self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.push(CertFiles {
+            hosts: Vec::new(),
+            cert: cert.into(),
+            key: key.into(),
+        })
+    }
+
+    /// the certificate for a host, which may be a wildcard like `*.example.test`
+    pub fn host_cert(
+        self,
+        host: impl Into<String>,
+        cert: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+    ) -> Self {
+        self.push(CertFiles {
+            hosts: vec![host.into()],
+            cert: cert.into(),
+            key: key.into(),
+        })
+    }
+
+    /// time a client has to complete the handshake, 10 seconds by default
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// serve the certificates of another listener from this one, e.g. when tenants change,
+    /// the running listener loads them with its next check for changed files
+    pub fn replace(&self, other: &TlsListener) {
+        self.files.store(other.files.load_full());
+    }
+
+    fn push(self, files: CertFiles) -> Self {
+        let mut list = self.files.load().to_vec();
+        list.push(files);
+        self.files.store(Arc::new(list));
+        self
+    }
+
+    pub(crate) async fn bind(self) -> anyhow::Result<BoundTls> {
+        let resolver = Arc::new(CertResolver::try_new(self.files)?);
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", self.port)).await?;
+        Ok(BoundTls {
+            listener,
+            resolver,
+            handshake_timeout: self.handshake_timeout,
+        })
+    }
+}
+
+impl CertResolver {
+    fn try_new(files: Arc<ArcSwap<Vec<CertFiles>>>) -> anyhow::Result<Self> {
+        let certs = Certs::load(files.load_full())?;
+        Ok(Self {
+            files,
+            certs: ArcSwap::from_pointee(certs),
+        })
+    }
+
+    /// load the certificates again if any of their files changed or files were replaced,
+    /// a broken file keeps the previous certificates in use
+    pub(crate) fn reload(&self) -> anyhow::Result<bool> {
+        let files = self.files.load_full();
+        let certs = self.certs.load();
+        if files == certs.files && modified(&files) == certs.modified {
+            return Ok(false);
+        }
+        self.certs.store(Arc::new(Certs::load(files)?));
+        Ok(true)
+    }
+
+    fn config(self: &Arc<Self>) -> anyhow::Result<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certs = self.certs.load();
+        hello
+            .server_name()
+            .and_then(|name| certs.get(name))
+            .or_else(|| certs.default.clone())
+    }
+}
+
+impl Certs {
+    fn load(files: Arc<Vec<CertFiles>>) -> anyhow::Result<Self> {
+        if files.is_empty() {
+            bail!("a tls listener needs at least one certificate");
+        }
+        let mut certs = Certs {
+            modified: modified(&files),
+            files: files.clone(),
+            ..Default::default()
+        };
+        for file in files.iter() {
+            let key = Arc::new(load_cert(file)?);
+            if file.hosts.is_empty() {
+                certs.default = Some(key.clone());
+            }
+            for host in &file.hosts {
+                let host = host.trim_end_matches('.').to_ascii_lowercase();
+                match host.strip_prefix('*') {
+                    Some(suffix) => certs.wildcards.push((suffix.to_string(), key.clone())),
+                    None => {
+                        certs.exact.insert(host, key.clone());
+                    }
+                }
+            }
+        }
+        certs
+            .wildcards
+            .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        Ok(certs)
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let name = name.to_ascii_lowercase();
+        self.exact.get(&name).cloned().or_else(|| {
+            self.wildcards
+                .iter()
+                .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix.as_str()))
+                .map(|(_, key)| key.clone())
+        })
+    }
+}
+
+fn load_cert(files: &CertFiles) -> anyhow::Result<CertifiedKey> {
+    let open = |path: &PathBuf| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open {}", path.display()))
+    };
+    let chain = rustls_pemfile::certs(&mut open(&files.cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid certificate {}", files.cert.display()))?;
+    if chain.is_empty() {
+        bail!("no certificate in {}", files.cert.display());
+    }
+    let key = rustls_pemfile::private_key(&mut open(&files.key)?)
+        .with_context(|| format!("invalid private key {}", files.key.display()))?
+        .with_context(|| format!("no private key in {}", files.key.display()))?;
+    let key = any_supported_type(&key)
+        .with_context(|| format!("unsupported private key {}", files.key.display()))?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+fn modified(files: &[CertFiles]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .flat_map(|f| [&f.cert, &f.key])
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// serve the app over tls, checking the certificate files for changes in the background
+pub(crate) async fn serve(bound: BoundTls, app: Router) -> anyhow::Result<()> {
+    let BoundTls {
+        listener,
+        resolver,
+        handshake_timeout,
+    } = bound;
+    let acceptor = TlsAcceptor::from(Arc::new(resolver.config()?));
+    info!("listening on {} (tls)", listener.local_addr()?);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CERT_RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            match resolver.reload() {
+                Ok(true) => info!("reloaded tls certificates"),
+                Ok(false) => {}
+                Err(e) => warn!("failed to reload tls certificates: {:?}", e),
+            }
+        }
+    });
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => return warn!("tls handshake failed: {}", e),
+                    Err(_) => return warn!("tls handshake timed out"),
+                };
+            let ret = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+            if let Err(e) = ret {
+                warn!("failed to serve connection: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use axum::routing::get;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+
+    use super::*;
+
+    struct Ca {
+        cert: Certificate,
+        key: KeyPair,
+    }
+
+    impl Ca {
+        fn new() -> anyhow::Result<Self> {
+            let key = KeyPair::generate()?;
+            let mut params = CertificateParams::new(Vec::<String>::new())?;
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let cert = params.self_signed(&key)?;
+            Ok(Self { cert, key })
+        }
+
+        /// write a certificate for a host signed by the ca, returning its der
+        fn issue(&self, host: &str, dir: &Path, name: &str) -> anyhow::Result<Vec<u8>> {
+            let key = KeyPair::generate()?;
+            let cert = CertificateParams::new(vec![host.to_string()])?
+                .signed_by(&key, &self.cert, &self.key)?;
+            fs::write(dir.join(format!("{name}.pem")), cert.pem())?;
+            fs::write(dir.join(format!("{name}.key")), key.serialize_pem())?;
+            Ok(cert.der().to_vec())
+        }
+    }
+
+    /// serve a listener on a random local port
+    async fn start(tls: &TlsListener) -> anyhow::Result<(Arc<CertResolver>, u16)> {
+        let resolver = Arc::new(CertResolver::try_new(tls.files.clone())?);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let bound = BoundTls {
+            listener,
+            resolver: resolver.clone(),
+            handshake_timeout: tls.handshake_timeout,
+        };
+        tokio::spawn(serve(bound, app));
+        Ok((resolver, port))
+    }
+
+    /// the certificate the server presents for a host, after a request over the connection
+    async fn handshake(ca: &Ca, port: u16, host: &str) -> anyhow::Result<(Vec<u8>, String)> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert.der().clone())?;
+        let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+        let name = ServerName::try_from(host.to_string())?;
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(name, stream)
+            .await?;
+        let cert = stream.get_ref().1.peer_certificates().unwrap()[0].to_vec();
+        let req = format!("GET / HTTP/1.1\r\nhost: {host}\r\nconnection: close\r\n\r\n");
+        stream.write_all(req.as_bytes()).await?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.ok();
+        Ok((cert, res))
+    }
+
+    #[tokio::test]
+    async fn tls_should_pick_and_reload_certs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        let ca = Ca::new()?;
+        let default = ca.issue("localhost", dir.path(), "default")?;
+        let api = ca.issue("*.api.test", dir.path(), "api")?;
+
+        let tls = TlsListener::new(0)
+            .cert(path("default.pem"), path("default.key"))
+            .host_cert("*.api.test", path("api.pem"), path("api.key"));
+        let (resolver, port) = start(&tls).await?;
+
+        let (cert, res) = handshake(&ca, port, "v1.api.test").await?;
+        assert_eq!(cert, api);
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.ends_with("hello"));
+        assert_eq!(handshake(&ca, port, "localhost").await?.0, default);
+
+        assert!(!resolver.reload()?);
+        let renewed = ca.issue("*.api.test", dir.path(), "api")?;
+        File::options()
+            .write(true)
+            .open(path("api.pem"))?
+            .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+        assert!(resolver.reload()?);
+        assert_eq!(handshake(&ca, port, "v2.api.test").await?.0, renewed);
+
+        // a broken file keeps the working certificates
+        fs::write(path("api.key"), "broken")?;
+        File::options()
+            .write(true)
+            .open(path("api.key"))?
+            .set_modified(SystemTime::now() + Duration::from_secs(120))?;
+        assert!(resolver.reload().is_err());
+        assert_eq!(handshake(&ca, port, "v2.api.test").await?.0, renewed);
+
+        // replaced files are picked up without touching them
+        let web = ca.issue("web.test", dir.path(), "web")?;
+        let replaced = TlsListener::new(0)
+            .cert(path("default.pem"), path("default.key"))
+            .host_cert("web.test", path("web.pem"), path("web.key"));
+        tls.replace(&replaced);
+        assert!(resolver.reload()?);
+        assert_eq!(handshake(&ca, port, "web.test").await?.0, web);
+        // the api hosts get the default certificate now, which the client refuses
+        assert!(handshake(&ca, port, "v2.api.test").await.is_err());
+        assert_eq!(handshake(&ca, port, "localhost").await?.0, default);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_handshakes_should_time_out() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let ca = Ca::new()?;
+        ca.issue("localhost", dir.path(), "default")?;
+        let tls = TlsListener::new(0)
+            .cert(
+                dir.path().join("default.pem"),
+                dir.path().join("default.key"),
+            )
+            .handshake_timeout(Duration::from_millis(100));
+        let (_, port) = start(&tls).await?;
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await?;
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf));
+        assert_eq!(read.await??, 0);
+        Ok(())
+    }
+}
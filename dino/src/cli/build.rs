@@ -5,12 +5,16 @@ use clap::Parser;
 use crate::{utils::build_project, CmdExecutor};
 
 #[derive(Debug, Parser)]
-pub struct BuildOpts {}
+pub struct BuildOpts {
+    /// merge `config.<profile>.yml` over `config.yml`
+    #[arg(long)]
+    pub profile: Option<String>,
+}
 
 impl CmdExecutor for BuildOpts {
     async fn execute(self) -> anyhow::Result<()> {
         let cur_dir = env::current_dir()?.display().to_string();
-        let filename = build_project(&cur_dir)?;
+        let filename = build_project(&cur_dir, self.profile.as_deref())?;
         eprintln!("Build success: {}", filename);
         Ok(())
     }
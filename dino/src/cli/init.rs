@@ -5,7 +5,7 @@ use clap::Parser;
 use dialoguer::Input;
 use git2::Repository;
 
-use crate::CmdExecutor;
+use crate::{utils::write_types, CmdExecutor};
 
 #[derive(Template)]
 #[template(path = "config.yml.j2")]
@@ -45,5 +45,6 @@ fn init_project(name: &str, path: &Path) -> anyhow::Result<()> {
     fs::write(path.join("config.yml"), config.render()?)?;
     fs::write(path.join("main.ts"), MainTsFile {}.render()?)?;
     fs::write(path.join(".gitignore"), GitIgnoreFile {}.render()?)?;
+    write_types(path)?;
     Ok(())
 }
@@ -1,10 +1,14 @@
 mod build;
 mod init;
+mod rollback;
 mod run;
+mod serve;
 
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
-pub use {build::BuildOpts, init::InitOpts, run::RunOpts};
+pub use {
+    build::BuildOpts, init::InitOpts, rollback::RollbackOpts, run::RunOpts, serve::ServeOpts,
+};
 
 #[derive(Debug, Parser)]
 #[command(name="dino", version, author, about, long_about=None)]
@@ -22,4 +26,14 @@ pub enum SubCommand {
     Build(BuildOpts),
     #[command(name = "run", about = "Run user's dino project")]
     Run(RunOpts),
+    #[command(
+        name = "serve",
+        about = "Serve many dino projects from a directory or a tenants file"
+    )]
+    Serve(ServeOpts),
+    #[command(
+        name = "rollback",
+        about = "Serve a previous version of a project through the admin api of a server"
+    )]
+    Rollback(RollbackOpts),
 }
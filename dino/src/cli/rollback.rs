@@ -0,0 +1,95 @@
+use anyhow::Context as _;
+use clap::Parser;
+use dino_server::TenantInfo;
+use serde_json::json;
+
+use crate::CmdExecutor;
+
+#[derive(Debug, Parser)]
+pub struct RollbackOpts {
+    /// name of the project, as deployed through the admin api
+    pub name: String,
+    /// version to serve again, one of the hashes listed by the admin api
+    pub hash: String,
+    /// url of the admin api of the server, like `http://127.0.0.1:9000`
+    #[arg(long, env = "DINO_ADMIN_URL")]
+    pub admin: String,
+    /// token of the admin api
+    #[arg(long, env = "DINO_ADMIN_TOKEN", hide_env_values = true)]
+    pub token: String,
+}
+
+impl CmdExecutor for RollbackOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/tenants/{}/rollback",
+            self.admin.trim_end_matches('/'),
+            self.name
+        );
+        let auth = format!("Bearer {}", self.token);
+        let body = json!({ "hash": self.hash });
+        let (name, admin) = (self.name, self.admin);
+        let info = tokio::task::spawn_blocking(move || {
+            let res = match ureq::post(&url).set("authorization", &auth).send_json(body) {
+                Ok(res) => res,
+                Err(ureq::Error::Status(status, res)) => {
+                    let msg = res.into_string().unwrap_or_default();
+                    anyhow::bail!("failed to roll back {name}: {status} {msg}");
+                }
+                Err(e) => return Err(e).with_context(|| format!("failed to reach {admin}")),
+            };
+            anyhow::Ok(res.into_json::<TenantInfo>()?)
+        })
+        .await??;
+        eprintln!("Rolled back {} to {}", info.name, info.hash);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dino_server::{
+        DinoServer, ProjectConfig, SwappableAppRouter, SwappableTenants, Tenants, TenentRouter,
+    };
+
+    use super::*;
+
+    fn free_port() -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn rollback_should_call_the_admin_api() -> anyhow::Result<()> {
+        let code = "(function(){ function hello(){} return { hello }; })();";
+        let config = |path: &str| {
+            ProjectConfig::parse(&format!(
+                "{{ name: billing, routes: {{ /{path}: [{{ method: GET, handler: hello }}] }} }}"
+            ))
+        };
+        let router = SwappableAppRouter::try_new(code, config("v1")?)?;
+        let v1 = router.load().hash.clone();
+        router.swap(code, config("v2")?)?;
+        let tenants = Tenants::try_new(vec![TenentRouter::new("billing", router.clone())], None)?;
+        let admin = free_port();
+        let server = DinoServer::new(free_port())
+            .swappable_tenants(SwappableTenants::new(tenants))
+            .admin(admin, "secret");
+        tokio::spawn(server.serve());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let opts = |hash: &str, token: &str| RollbackOpts {
+            name: "billing".to_string(),
+            hash: hash.to_string(),
+            admin: format!("http://127.0.0.1:{admin}/"),
+            token: token.to_string(),
+        };
+        assert!(opts(&v1, "wrong").execute().await.is_err());
+        assert!(opts("missing", "secret").execute().await.is_err());
+        opts(&v1, "secret").execute().await?;
+        assert_eq!(router.load().hash, v1);
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
-use std::{fs, path::Path, time::Duration};
+use std::{path::Path, time::Duration};
 
 use clap::Parser;
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use dino_server::{DinoServer, SwappableAppRouter, TenentRouter};
 use notify::RecursiveMode;
 use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 use tokio::sync::mpsc::channel;
@@ -9,14 +9,17 @@ use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use tracing::{level_filters::LevelFilter, warn};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
-use crate::{utils::build_project, CmdExecutor};
+use crate::{utils::load_project, CmdExecutor, BUILD_DIR, PUBLIC_DIR};
 
-const MONITOR_FS_INTERVAL: Duration = Duration::from_secs(2);
+pub(crate) const MONITOR_FS_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Parser)]
 pub struct RunOpts {
     #[arg(short, long, default_value_t = 3000)]
     pub port: u16,
+    /// merge `config.<profile>.yml` over `config.yml`
+    #[arg(long)]
+    pub profile: Option<String>,
 }
 
 impl CmdExecutor for RunOpts {
@@ -24,19 +27,28 @@ impl CmdExecutor for RunOpts {
         let layer = Layer::new().with_filter(LevelFilter::INFO);
         tracing_subscriber::registry().with(layer).init();
 
-        let (code, config) = get_code_and_config()?;
+        let (code, config) = load_project(".", self.profile.as_deref())?;
 
-        let router = SwappableAppRouter::try_new(&code, config.routes)?;
-        let routers = vec![TenentRouter::new("localhost", router.clone())];
+        let router = SwappableAppRouter::try_new(&code, config)?;
+        let tenant = TenentRouter::new("localhost", router.clone()).alias("127.0.0.1");
 
-        tokio::spawn(async_watch(".", router));
+        tokio::spawn(async_watch(".", router.clone(), self.profile));
 
-        start_server(self.port, routers).await?;
+        // the dev server answers any host, e.g. a lan ip
+        DinoServer::new(self.port)
+            .tenant(tenant)
+            .default_tenant(router)
+            .serve()
+            .await?;
         Ok(())
     }
 }
 
-async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow::Result<()> {
+async fn async_watch(
+    p: impl AsRef<Path>,
+    router: SwappableAppRouter,
+    profile: Option<String>,
+) -> anyhow::Result<()> {
     let (tx, rx) = channel(1);
 
     let mut debouncer = new_debouncer(MONITOR_FS_INTERVAL, move |res: DebounceEventResult| {
@@ -51,19 +63,14 @@ async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow:
     while let Some(ret) = stream.next().await {
         match ret {
             Ok(events) => {
-                let mut need_swap = false;
-                for event in events {
-                    let path = event.path;
-                    let ext = path.extension().unwrap_or_default();
-                    if path.ends_with("config.yml") || ext == "ts" || ext == "js" {
-                        need_swap = true;
+                if events.iter().any(|event| is_project_source(&event.path)) {
+                    // keep serving the last good build until the project is fixed
+                    let ret = load_project(".", profile.as_deref())
+                        .and_then(|(code, config)| router.swap(code, config));
+                    if let Err(e) = ret {
+                        warn!("failed to rebuild project: {:?}", e);
                     }
                 }
-
-                if need_swap {
-                    let (code, config) = get_code_and_config()?;
-                    router.swap(code, config.routes)?;
-                }
             }
             Err(e) => {
                 warn!("Error: {:?}", e);
@@ -73,10 +80,16 @@ async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow:
     Ok(())
 }
 
-fn get_code_and_config() -> Result<(String, ProjectConfig), anyhow::Error> {
-    let filename = build_project(".")?;
-    let config = filename.replace(".mjs", ".yml");
-    let code = fs::read_to_string(filename)?;
-    let config = ProjectConfig::load(config)?;
-    Ok((code, config))
+/// whether a changed file is part of the project and needs a rebuild
+pub(crate) fn is_project_source(path: &Path) -> bool {
+    let in_dir = |dir: &str| path.components().any(|c| c.as_os_str() == dir);
+    if in_dir(BUILD_DIR) {
+        return false;
+    }
+    let ext = path.extension().unwrap_or_default();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (name.starts_with("config.") && ext == "yml")
+        || ext == "ts"
+        || ext == "js"
+        || in_dir(PUBLIC_DIR)
 }
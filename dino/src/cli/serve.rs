@@ -0,0 +1,420 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use clap::Parser;
+use dino_server::{
+    DinoServer, SwappableAppRouter, SwappableTenants, Tenants, TenentRouter, TlsListener,
+};
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use serde::Deserialize;
+use tokio::sync::mpsc::channel;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+
+use super::run::{is_project_source, MONITOR_FS_INTERVAL};
+use crate::{utils::load_project, CmdExecutor};
+
+#[derive(Debug, Parser)]
+pub struct ServeOpts {
+    #[arg(short, long, default_value_t = 3000)]
+    pub port: u16,
+    /// directory of projects, every subdirectory with a `config.yml` is served under its name
+    #[arg(default_value = ".")]
+    pub dir: String,
+    /// yaml file listing the projects to serve and their hosts, instead of scanning `dir`
+    #[arg(long)]
+    pub tenants: Option<String>,
+    /// merge `config.<profile>.yml` over `config.yml` of every project
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// also serve https on this port
+    #[arg(long, requires_all = ["cert", "key"])]
+    pub tls_port: Option<u16>,
+    /// pem certificate chain for hosts without a certificate in the tenants file
+    #[arg(long)]
+    pub cert: Option<String>,
+    /// pem private key of `--cert`
+    #[arg(long)]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenantsFile {
+    tenants: Vec<TenantConfig>,
+}
+
+/// a project served by `dino serve`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct TenantConfig {
+    /// the project directory, relative to the tenants file
+    path: PathBuf,
+    /// hosts of the project, its directory name by default
+    #[serde(default)]
+    hosts: Vec<String>,
+    /// path prefix the project is mounted at on its hosts
+    #[serde(default)]
+    prefix: Option<String>,
+    /// certificate of the project hosts, relative to the tenants file
+    #[serde(default)]
+    tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+/// the projects being served, keyed by their canonical directory
+struct Projects {
+    dir: PathBuf,
+    tenants_file: Option<PathBuf>,
+    profile: Option<String>,
+    served: BTreeMap<PathBuf, (TenantConfig, SwappableAppRouter)>,
+    tenants: SwappableTenants,
+    /// the https listener, its certificates follow the tenants file
+    tls: Option<TlsListener>,
+    /// the default certificate and key of the https listener
+    cert: Option<(String, String)>,
+}
+
+impl CmdExecutor for ServeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let layer = Layer::new().with_filter(LevelFilter::INFO);
+        tracing_subscriber::registry().with(layer).init();
+
+        let mut projects = Projects::try_new(&self)?;
+        projects.sync().await?;
+        let tenants = projects.tenants.clone();
+        let tls = projects.tls.clone();
+
+        tokio::spawn(async_watch(projects));
+
+        let server = DinoServer::new(self.port).swappable_tenants(tenants);
+        match tls {
+            Some(listener) => server.tls(listener),
+            None => server,
+        }
+        .serve()
+        .await?;
+        Ok(())
+    }
+}
+
+impl Projects {
+    fn try_new(opts: &ServeOpts) -> anyhow::Result<Self> {
+        let canonical =
+            |path: &str| fs::canonicalize(path).with_context(|| format!("failed to open {path}"));
+        Ok(Self {
+            dir: canonical(&opts.dir)?,
+            tenants_file: opts.tenants.as_deref().map(canonical).transpose()?,
+            profile: opts.profile.clone(),
+            served: BTreeMap::new(),
+            tenants: SwappableTenants::default(),
+            tls: opts.tls_port.map(TlsListener::new),
+            cert: opts.cert.clone().zip(opts.key.clone()),
+        })
+    }
+
+    /// the projects listed in the tenants file, or the subdirectories of the projects dir
+    fn discover(&self) -> anyhow::Result<Vec<TenantConfig>> {
+        let mut found = Vec::new();
+        match &self.tenants_file {
+            Some(file) => {
+                let content = fs::read_to_string(file)?;
+                let base = file.parent().unwrap_or(Path::new("."));
+                for mut tenant in parse_tenants(&content, base)? {
+                    tenant.path = fs::canonicalize(&tenant.path)
+                        .with_context(|| format!("project {} not found", tenant.path.display()))?;
+                    found.push(tenant);
+                }
+            }
+            None => {
+                for entry in fs::read_dir(&self.dir)? {
+                    let path = entry?.path();
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    if !name.starts_with('.') && path.join("config.yml").is_file() {
+                        found.push(TenantConfig {
+                            hosts: vec![name.to_string()],
+                            path,
+                            prefix: None,
+                            tls: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// load added projects, drop removed ones and swap the tenants of the server,
+    /// a project failing to build is left out until it is fixed, one whose hosts are
+    /// taken by a project listed before it until they are free
+    async fn sync(&mut self) -> anyhow::Result<()> {
+        let found = self.discover()?;
+        self.update_tls(&found);
+        let mut served = BTreeMap::new();
+        let mut order = Vec::new();
+        for tenant in found {
+            let router = match self.served.get(&tenant.path) {
+                Some((_, router)) => router.clone(),
+                None => match self.load(&tenant.path).await {
+                    Ok(router) => {
+                        info!("serving project {}", tenant.path.display());
+                        router
+                    }
+                    Err(e) => {
+                        warn!("failed to load project {}: {:?}", tenant.path.display(), e);
+                        continue;
+                    }
+                },
+            };
+            order.push(tenant.path.clone());
+            served.insert(tenant.path.clone(), (tenant, router));
+        }
+        if served.keys().eq(self.served.keys())
+            && served
+                .values()
+                .map(|(t, _)| t)
+                .eq(self.served.values().map(|(t, _)| t))
+        {
+            return Ok(());
+        }
+        for dir in self.served.keys().filter(|dir| !served.contains_key(*dir)) {
+            info!("removed project {}", dir.display());
+        }
+        let mut routers = Vec::new();
+        for dir in &order {
+            let (tenant, router) = &served[dir];
+            routers.push(tenant.tenant(router.clone()));
+            if let Err(e) = Tenants::try_new(routers.clone(), None) {
+                warn!("skip project {}: {:?}", dir.display(), e);
+                routers.pop();
+            }
+        }
+        self.tenants
+            .swap(Tenants::try_new(routers, None)?.name_aliases(true));
+        self.served = served;
+        Ok(())
+    }
+
+    /// rebuild the projects with changed sources
+    async fn rebuild(&self, paths: &[PathBuf]) {
+        for (dir, (_, router)) in &self.served {
+            let changed = paths
+                .iter()
+                .any(|path| path.starts_with(dir) && is_project_source(path));
+            if !changed {
+                continue;
+            }
+            let (project, profile) = (dir.display().to_string(), self.profile.clone());
+            let router = router.clone();
+            let ret = tokio::task::spawn_blocking(move || {
+                load_project(&project, profile.as_deref())
+                    .and_then(|(code, config)| router.swap(code, config))
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+            if let Err(e) = ret {
+                warn!("failed to rebuild project {}: {:?}", dir.display(), e);
+            }
+        }
+    }
+
+    /// build and evaluate a project off the async workers, bundling may take seconds
+    async fn load(&self, dir: &Path) -> anyhow::Result<SwappableAppRouter> {
+        let (project, profile) = (dir.display().to_string(), self.profile.clone());
+        tokio::task::spawn_blocking(move || {
+            let (code, config) = load_project(&project, profile.as_deref())?;
+            SwappableAppRouter::try_new(code, config)
+        })
+        .await?
+    }
+
+    /// point the https listener at the default certificate and the ones of the tenants file,
+    /// projects failing to load included, it picks them up with its next check for changes
+    fn update_tls(&self, tenants: &[TenantConfig]) {
+        let (Some(tls), Some((cert, key))) = (&self.tls, &self.cert) else {
+            return;
+        };
+        // only the certificates of this listener are used, not its port
+        let certs = tenants
+            .iter()
+            .filter_map(|tenant| tenant.tls.as_ref().map(|tls| (&tenant.hosts, tls)))
+            .flat_map(|(hosts, tls)| hosts.iter().map(move |host| (host, tls)))
+            .fold(
+                TlsListener::new(0).cert(cert, key),
+                |listener, (host, tls)| {
+                    listener.host_cert(host.clone(), tls.cert.clone(), tls.key.clone())
+                },
+            );
+        tls.replace(&certs);
+    }
+
+    /// the project dirs watched on their own, a scanned projects dir is watched as a whole
+    fn watched_dirs(&self) -> BTreeSet<PathBuf> {
+        match self.tenants_file {
+            Some(_) => self.served.keys().cloned().collect(),
+            None => BTreeSet::new(),
+        }
+    }
+}
+
+impl TenantConfig {
+    fn tenant(&self, router: SwappableAppRouter) -> TenentRouter {
+        let mut hosts = self.hosts.iter();
+        let first = hosts.next().cloned().unwrap_or_default();
+        let tenant = hosts.fold(TenentRouter::new(first, router), |t, host| {
+            t.alias(host.clone())
+        });
+        match &self.prefix {
+            Some(prefix) => tenant.prefix(prefix.clone()),
+            None => tenant,
+        }
+    }
+}
+
+/// the tenants of a tenants file, with paths joined to its directory
+fn parse_tenants(content: &str, base: &Path) -> anyhow::Result<Vec<TenantConfig>> {
+    let file: TenantsFile = serde_yaml::from_str(content).context("invalid tenants file")?;
+    let mut tenants = file.tenants;
+    for tenant in &mut tenants {
+        if tenant.hosts.is_empty() {
+            let name = tenant.path.file_name().unwrap_or_default();
+            tenant.hosts.push(name.to_string_lossy().to_string());
+        }
+        tenant.path = base.join(&tenant.path);
+        if let Some(tls) = &mut tenant.tls {
+            tls.cert = base.join(&tls.cert);
+            tls.key = base.join(&tls.key);
+        }
+    }
+    Ok(tenants)
+}
+
+/// rebuild projects as their sources change and pick up added or removed projects
+async fn async_watch(mut projects: Projects) -> anyhow::Result<()> {
+    let (tx, rx) = channel(1);
+
+    let mut debouncer = new_debouncer(MONITOR_FS_INTERVAL, move |res: DebounceEventResult| {
+        tx.blocking_send(res).unwrap()
+    })?;
+
+    // the tenants file is watched through its directory as editors replace files on save
+    let root = match &projects.tenants_file {
+        Some(file) => (
+            file.parent().unwrap_or(&projects.dir),
+            RecursiveMode::NonRecursive,
+        ),
+        None => (projects.dir.as_path(), RecursiveMode::Recursive),
+    };
+    debouncer.watcher().watch(root.0, root.1)?;
+    let mut watched = BTreeSet::new();
+
+    let mut stream = ReceiverStream::new(rx);
+    loop {
+        let dirs = projects.watched_dirs();
+        for dir in watched.difference(&dirs) {
+            debouncer.watcher().unwatch(dir)?;
+        }
+        for dir in dirs.difference(&watched) {
+            debouncer.watcher().watch(dir, RecursiveMode::Recursive)?;
+        }
+        watched = dirs;
+
+        let Some(ret) = stream.next().await else {
+            break;
+        };
+        match ret {
+            Ok(events) => {
+                let paths: Vec<_> = events.into_iter().map(|e| e.path).collect();
+                if let Err(e) = projects.sync().await {
+                    warn!("failed to update projects: {:?}", e);
+                }
+                projects.rebuild(&paths).await;
+            }
+            Err(e) => {
+                warn!("Error: {:?}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tenants_should_work() -> anyhow::Result<()> {
+        let content = r#"
+        tenants:
+          - path: billing
+            hosts: [billing.example.test, "*.billing.example.test"]
+          - path: ../search
+            hosts: [example.test]
+            prefix: /tenants/search
+            tls: { cert: certs/search.pem, key: certs/search.key }
+          - path: blog
+        "#;
+        let tenants = parse_tenants(content, Path::new("/srv/dino"))?;
+        assert_eq!(tenants.len(), 3);
+        assert_eq!(tenants[0].path, PathBuf::from("/srv/dino/billing"));
+        assert_eq!(tenants[1].prefix.as_deref(), Some("/tenants/search"));
+        assert_eq!(
+            tenants[1].tls.as_ref().map(|tls| tls.cert.clone()),
+            Some(PathBuf::from("/srv/dino/certs/search.pem"))
+        );
+        assert_eq!(tenants[2].hosts, vec!["blog".to_string()]);
+
+        assert!(parse_tenants("tenants: [{ hosts: [a] }]", Path::new(".")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn conflicting_projects_should_be_skipped() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for name in ["billing", "search", "blog"] {
+            let project = root.join(name);
+            fs::create_dir_all(&project)?;
+            let config =
+                format!("name: {name}\nroutes:\n  /:\n    - method: GET\n      handler: hello\n");
+            fs::write(project.join("config.yml"), config)?;
+            fs::write(
+                project.join("main.ts"),
+                "export function hello() { return 'hi'; }\n",
+            )?;
+        }
+        let tenants = root.join("tenants.yml");
+        fs::write(
+            &tenants,
+            "tenants:\n  - { path: billing, hosts: [app.test] }\n  - { path: search, hosts: [app.test] }\n  - path: blog\n",
+        )?;
+        let opts = ServeOpts {
+            port: 0,
+            dir: root.display().to_string(),
+            tenants: Some(tenants.display().to_string()),
+            profile: None,
+            tls_port: None,
+            cert: None,
+            key: None,
+        };
+        let mut projects = Projects::try_new(&opts)?;
+        projects.sync().await?;
+        let served = projects.tenants.load();
+        let names: Vec<_> = served
+            .tenants()
+            .iter()
+            .map(|t| t.router().load().name.clone())
+            .collect();
+        assert_eq!(names, vec!["billing".to_string(), "blog".to_string()]);
+        Ok(())
+    }
+}
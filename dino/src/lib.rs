@@ -5,6 +5,8 @@ mod utils;
 pub use cli::*;
 
 pub const BUILD_DIR: &str = ".build";
+pub const TYPES_FILE: &str = "dino.d.ts";
+pub const PUBLIC_DIR: &str = "public";
 
 #[allow(async_fn_in_trait)]
 #[enum_dispatch]
@@ -1,15 +1,15 @@
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     fs::{self, File},
-    io,
     path::{Path, PathBuf},
 };
 
 use anyhow::Result;
 use bundler::run_bundle;
+use dino_server::{ts_declarations, validate_handlers, ProjectConfig, ASSETS_MANIFEST};
 use glob::{glob, GlobError};
 
-use crate::BUILD_DIR;
+use crate::{BUILD_DIR, PUBLIC_DIR, TYPES_FILE};
 
 pub(crate) fn get_files_with_exts(dir: &str, exts: &[&str]) -> Result<BTreeSet<PathBuf>> {
     let mut files = BTreeSet::new();
@@ -36,13 +36,25 @@ pub(crate) fn get_files_with_exts(dir: &str, exts: &[&str]) -> Result<BTreeSet<P
     //     .collect::<Result<BTreeSet<PathBuf>, _>>()?;
     Ok(files)
 }
-pub(crate) fn calc_project_hash(dir: &str) -> Result<String> {
-    calc_hash_for_files(dir, &["ts", "js", "json"], 16)
+/// hash the project sources together with the resolved config and the public files, if any,
+/// leaving out dot dirs like the build dir so earlier builds don't change the hash
+pub(crate) fn calc_project_hash(dir: &str, config: &str, public: Option<&Path>) -> Result<String> {
+    let mut files = get_files_with_exts(dir, &["ts", "js", "json"])?;
+    files.retain(|file| !is_hidden(file.strip_prefix(dir).unwrap_or(file)));
+    if let Some(public) = public {
+        files.extend(get_public_files(public)?);
+    }
+    hash_files(files, config.as_bytes(), 16)
+}
+
+fn is_hidden(rel: &Path) -> bool {
+    rel.components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
 }
 
-pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Result<String> {
-    let files = get_files_with_exts(dir, exts)?;
+fn hash_files(files: BTreeSet<PathBuf>, seed: &[u8], len: usize) -> Result<String> {
     let mut hasher = blake3::Hasher::new();
+    hasher.update(seed);
     // for file in files {
     //     hasher.update_reader(File::open(file)?)?;
     // }
@@ -57,21 +69,191 @@ pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Resul
         .take(len)
         .collect())
 }
-pub(crate) fn build_project(dir: &str) -> Result<String> {
-    let hash = calc_project_hash(dir)?;
-    let filename = format!("{}/{}.mjs", BUILD_DIR, hash);
-    let config = format!("{}/{}.yml", BUILD_DIR, hash);
+/// write the handler type declarations into the project, only touching the file if it changed
+pub(crate) fn write_types(dir: impl AsRef<Path>) -> Result<()> {
+    let path = dir.as_ref().join(TYPES_FILE);
+    let content = ts_declarations();
+    if fs::read_to_string(&path).ok().as_deref() != Some(content.as_str()) {
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// bundle the project into the build dir along with its config, resolved for the profile
+pub(crate) fn build_project(dir: &str, profile: Option<&str>) -> Result<String> {
+    write_types(dir)?;
+    let root = Path::new(dir);
+    let mut config = ProjectConfig::resolve(root.join("config.yml"), profile)?;
+    let public = public_dir(dir, &config);
+    let hash = calc_project_hash(dir, &serde_yaml::to_string(&config)?, public.as_deref())?;
+    let build_dir = root.join(BUILD_DIR);
+    let dst = build_dir.join(format!("{}.mjs", hash));
+    let filename = dst.display().to_string();
 
-    let dst = Path::new(&filename);
     if dst.exists() {
         return Ok(filename);
     }
 
-    let content = run_bundle("main.ts", &Default::default())?;
-    fs::write(dst, content)?;
-    let mut dst = File::create(config)?;
-    let mut src = File::open("config.yml")?;
-    io::copy(&mut src, &mut dst)?;
+    let entry = root.join("main.ts").display().to_string();
+    let content = run_bundle(&entry, &Default::default())?;
+    let project: ProjectConfig = serde_yaml::from_value(config.clone())?;
+    validate_handlers(&content, &project, &[])?;
+    fs::create_dir_all(&build_dir)?;
+    fs::write(&dst, content)?;
+    if let (Some(public), Some(map)) = (public, config.as_mapping_mut()) {
+        // relative to the project, see `load_project`
+        let packaged = format!("{}/{}.public", BUILD_DIR, hash);
+        package_assets(&public, &root.join(&packaged))?;
+        map.insert("public".into(), packaged.into());
+    }
+    fs::write(
+        build_dir.join(format!("{}.yml", hash)),
+        serde_yaml::to_string(&config)?,
+    )?;
 
     Ok(filename)
 }
+
+/// build the project and load its bundle and config, with the public dir resolved
+/// against the project dir so the project can be served from anywhere
+pub(crate) fn load_project(dir: &str, profile: Option<&str>) -> Result<(String, ProjectConfig)> {
+    let filename = build_project(dir, profile)?;
+    let code = fs::read_to_string(&filename)?;
+    let mut config = ProjectConfig::load(filename.replace(".mjs", ".yml"))?;
+    config.public = config.public.map(|public| Path::new(dir).join(public));
+    Ok((code, config))
+}
+
+/// the `public` directory of the config, or `public/` when it exists
+fn public_dir(dir: &str, config: &serde_yaml::Value) -> Option<PathBuf> {
+    match config.get("public").and_then(|v| v.as_str()) {
+        Some(public) => Some(Path::new(dir).join(public)),
+        None => {
+            let public = Path::new(dir).join(PUBLIC_DIR);
+            public.is_dir().then_some(public)
+        }
+    }
+}
+
+/// the files served from a public directory, leaving out hidden files and precompressed variants
+fn get_public_files(public: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![public.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let ext = path.extension().unwrap_or_default();
+            if name.starts_with('.') {
+                continue;
+            } else if path.is_dir() {
+                dirs.push(path);
+            } else if ext != "gz" && ext != "br" {
+                files.insert(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// copy the public files into `dst` under both their name and a fingerprinted name,
+/// writing the manifest the server uses to mark fingerprinted files immutable
+fn package_assets(public: &Path, dst: &Path) -> Result<()> {
+    let mut manifest = BTreeMap::new();
+    for file in get_public_files(public)? {
+        let rel = file.strip_prefix(public)?;
+        let hash = hash_files(BTreeSet::from([file.clone()]), &[], 8)?;
+        let fingerprinted = fingerprint(rel, &hash);
+        for (ext, required) in [("", true), (".gz", false), (".br", false)] {
+            let src = with_suffix(&file, ext);
+            if !required && !src.exists() {
+                continue;
+            }
+            for name in [rel, fingerprinted.as_path()] {
+                let target = with_suffix(&dst.join(name), ext);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&src, target)?;
+            }
+        }
+        manifest.insert(url_path(rel), url_path(&fingerprinted));
+    }
+    fs::create_dir_all(dst)?;
+    fs::write(
+        dst.join(ASSETS_MANIFEST),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(())
+}
+
+/// `css/app.css` becomes `css/app.<hash>.css`
+fn fingerprint(rel: &Path, hash: &str) -> PathBuf {
+    let stem = rel.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match rel.extension() {
+        Some(ext) => format!("{stem}.{hash}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{hash}"),
+    };
+    rel.with_file_name(name)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn url_path(rel: &Path) -> String {
+    let segments: Vec<_> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_should_work() {
+        let cases = [
+            ("app.css", "app.1a2b3c4d.css"),
+            ("js/app.min.js", "js/app.min.1a2b3c4d.js"),
+            ("LICENSE", "LICENSE.1a2b3c4d"),
+        ];
+        for (rel, expected) in cases {
+            assert_eq!(
+                fingerprint(Path::new(rel), "1a2b3c4d"),
+                PathBuf::from(expected)
+            );
+        }
+        assert_eq!(
+            url_path(Path::new("js/app.min.js")),
+            "/js/app.min.js".to_string()
+        );
+    }
+
+    #[test]
+    fn build_project_should_be_stable() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        fs::write(
+            root.join("config.yml"),
+            "name: app\nroutes:\n  /:\n    - method: GET\n      handler: hello\n",
+        )?;
+        fs::write(
+            root.join("main.ts"),
+            "export function hello() { return { status: 200, headers: {}, body: 'hi' }; }\n",
+        )?;
+        fs::create_dir_all(root.join("public/js"))?;
+        fs::write(root.join("public/js/app.js"), "console.log('hi');")?;
+
+        let dir = root.display().to_string();
+        let first = build_project(&dir, None)?;
+        assert!(root.join(BUILD_DIR).read_dir()?.count() > 0);
+        assert_eq!(build_project(&dir, None)?, first);
+        assert_eq!(build_project(&dir, None)?, first);
+        Ok(())
+    }
+}